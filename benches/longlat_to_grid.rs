@@ -0,0 +1,52 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use maidenhead::{longlat_to_grid, longlat_to_grid_const};
+use std::hint::black_box;
+
+// A spread of coordinates across the globe, so that the benchmark isn't
+// dominated by a single well-predicted path
+fn coords() -> Vec<(f64, f64)> {
+    (0..1000)
+        .map(|i| {
+            let f = i as f64 / 1000.0;
+            (f * 360.0 - 180.0, (f * 7.0 % 1.0) * 180.0 - 90.0)
+        })
+        .collect()
+}
+
+fn bench_longlat_to_grid(c: &mut Criterion) {
+    let coords = coords();
+
+    let mut group = c.benchmark_group("longlat_to_grid");
+    group.bench_function("dynamic/6", |b| {
+        b.iter(|| {
+            for &(long, lat) in &coords {
+                black_box(longlat_to_grid(black_box(long), black_box(lat), 6).unwrap());
+            }
+        })
+    });
+    group.bench_function("const/6", |b| {
+        b.iter(|| {
+            for &(long, lat) in &coords {
+                black_box(longlat_to_grid_const::<6>(black_box(long), black_box(lat)).unwrap());
+            }
+        })
+    });
+    group.bench_function("dynamic/10", |b| {
+        b.iter(|| {
+            for &(long, lat) in &coords {
+                black_box(longlat_to_grid(black_box(long), black_box(lat), 10).unwrap());
+            }
+        })
+    });
+    group.bench_function("const/10", |b| {
+        b.iter(|| {
+            for &(long, lat) in &coords {
+                black_box(longlat_to_grid_const::<10>(black_box(long), black_box(lat)).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_longlat_to_grid);
+criterion_main!(benches);
@@ -0,0 +1,83 @@
+// Without std there are no inherent float math methods on f64, so provide the
+// ones we use on top of libm. With std the inherent methods take precedence
+// and this trait is not compiled at all.
+
+pub(crate) trait FloatExt {
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn tan(self) -> Self;
+    fn atan(self) -> Self;
+    fn asin(self) -> Self;
+    fn acos(self) -> Self;
+    fn sinh(self) -> Self;
+    fn cosh(self) -> Self;
+    fn atanh(self) -> Self;
+    fn atan2(self, other: Self) -> Self;
+    fn sqrt(self) -> Self;
+    fn ln(self) -> Self;
+    fn powi(self, n: i32) -> Self;
+    fn round(self) -> Self;
+    fn floor(self) -> Self;
+}
+
+impl FloatExt for f64 {
+    fn sin(self) -> f64 {
+        libm::sin(self)
+    }
+
+    fn cos(self) -> f64 {
+        libm::cos(self)
+    }
+
+    fn tan(self) -> f64 {
+        libm::tan(self)
+    }
+
+    fn atan(self) -> f64 {
+        libm::atan(self)
+    }
+
+    fn asin(self) -> f64 {
+        libm::asin(self)
+    }
+
+    fn acos(self) -> f64 {
+        libm::acos(self)
+    }
+
+    fn sinh(self) -> f64 {
+        libm::sinh(self)
+    }
+
+    fn cosh(self) -> f64 {
+        libm::cosh(self)
+    }
+
+    fn atanh(self) -> f64 {
+        libm::atanh(self)
+    }
+
+    fn atan2(self, other: f64) -> f64 {
+        libm::atan2(self, other)
+    }
+
+    fn sqrt(self) -> f64 {
+        libm::sqrt(self)
+    }
+
+    fn ln(self) -> f64 {
+        libm::log(self)
+    }
+
+    fn powi(self, n: i32) -> f64 {
+        libm::pow(self, n as f64)
+    }
+
+    fn round(self) -> f64 {
+        libm::round(self)
+    }
+
+    fn floor(self) -> f64 {
+        libm::floor(self)
+    }
+}
@@ -1,28 +1,134 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use thiserror::Error;
 
+// The tests link std even without the std feature, which brings the inherent
+// float methods along
+#[cfg(all(not(feature = "std"), not(test)))]
+mod float;
+#[cfg(all(not(feature = "std"), not(test)))]
+use float::FloatExt;
+
 #[derive(Error, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum MHError {
     #[error("Invalid grid format `{0}`")]
     InvalidGrid(String),
-    #[error("Invalid grid length {0}, only 4/6/8/10 supported")]
+    #[error(
+        "Invalid character `{found}` at position {position} in grid `{grid}`, expected {}",
+        CharClass::at(*position)
+    )]
+    InvalidGridChar {
+        grid: String,
+        position: usize,
+        found: char,
+    },
+    #[error("Grid `{0}` is not in canonical casing")]
+    InvalidGridCase(String),
+    #[error("Invalid grid length {0}, only 2/4/6/8/10/12 supported")]
     InvalidGridLength(usize),
+    #[error("Invalid precision {0}, only 2/4/6/8/10/12 supported")]
+    InvalidPrecision(usize),
+    #[error("Grids of different precisions {0} and {1}")]
+    PrecisionMismatch(usize, usize),
     #[error("Invalid Longitude/Latitude: `{0}`/`{1}`")]
     InvalidLongLat(f64, f64),
+    #[error("Invalid degrees/minutes/seconds `{0}`")]
+    InvalidDms(String),
+    #[error("Invalid UTM coordinate `{0}`")]
+    InvalidUtm(String),
+    #[error("Grids `{0}` and `{1}` are in different fields")]
+    NoCommonGrid(String, String),
+    #[error("Invalid ADIF field `{0}`")]
+    InvalidAdif(String),
+    #[error("No grids given")]
+    NoGrids,
+    #[error("Invalid fraction {0}, must be between 0 and 1")]
+    InvalidFraction(f64),
+    #[error("Grids `{0}` and `{1}` are antipodal")]
+    AntipodalGrids(String, String),
+    #[error("Grid `{0}` round trips to `{1}`")]
+    RoundTripMismatch(String, String),
+    #[error("Invalid grid at index {0} of path: {1}")]
+    InvalidPathGrid(usize, #[source] Box<MHError>),
+    #[error("Buffer of {0} bytes is too small for a {1} character grid")]
+    BufferTooSmall(usize, usize),
     #[error("unknown error when generating grid string")]
     Unknown,
 }
 
+// What is allowed at each position of a grid string
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CharClass {
+    // A-R, for the field
+    FieldLetter,
+    // A-X, for the subsquare and superextended square
+    Letter,
+    // 0-9, for the square, extended and hyperextended square
+    Digit,
+}
+
+impl CharClass {
+    // The class of a given (0-based) position
+    pub fn at(position: usize) -> Self {
+        match position {
+            0 | 1 => CharClass::FieldLetter,
+            p if p % 4 < 2 => CharClass::Letter,
+            _ => CharClass::Digit,
+        }
+    }
+
+    // Whether a character is within the class, case insensitive
+    pub fn matches(self, c: char) -> bool {
+        match self {
+            CharClass::FieldLetter => ('A'..='R').contains(&c.to_ascii_uppercase()),
+            CharClass::Letter => ('A'..='X').contains(&c.to_ascii_uppercase()),
+            CharClass::Digit => c.is_ascii_digit(),
+        }
+    }
+}
+
+impl fmt::Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CharClass::FieldLetter => "a letter A-R",
+            CharClass::Letter => "a letter A-X",
+            CharClass::Digit => "a digit 0-9",
+        })
+    }
+}
+
+// Lets `?` propagate grid errors from functions returning io::Result
+#[cfg(feature = "std")]
+impl From<MHError> for std::io::Error {
+    fn from(err: MHError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, err)
+    }
+}
+
 // Grid squares are string representations of the latitude and longitude. A good introduction to how to calculate them is in:
 // http://www.w8bh.net/grid_squares.pdf
 //
-// The format is: FFSSssEEee
-// Field / Square / Subsquare / Extended Square / Superextended Square
+// The format is: FFSSssEEeeHH
+// Field / Square / Subsquare / Extended Square / Superextended Square / Hyperextended Square
 // Each covering for long/lat:
 // Field: 20 / 10 degrees for long / lat
 // Square: 2 / 1 degrees
 // Subsquare: 5 / 2.5 minutes
 // Extended Square: 30 / 15 seconds
 // Superextended Square: 1.25 / 0.625 seconds
+// Hyperextended Square: 0.125 / 0.0625 seconds
 // Note that the enumeration begins at south pole (so 90 degrees off on latitude) and is
 // unsigned positive, so needs to be subtracted by 180 to get +/- longitude.
 
@@ -39,39 +145,152 @@ static LONG_ESQ: f64 = 30.0 / 60.0 / 60.0;
 static LAT_ESQ: f64 = 15.0 / 60.0 / 60.0;
 static LONG_SESQ: f64 = 1.25 / 60.0 / 60.0;
 static LAT_SESQ: f64 = 0.625 / 60.0 / 60.0;
+static LONG_HSQ: f64 = 0.125 / 60.0 / 60.0;
+static LAT_HSQ: f64 = 0.0625 / 60.0 / 60.0;
 
-static LONG_MULT: [f64; 5] = [LONG_F, LONG_SQ, LONG_SSQ, LONG_ESQ, LONG_SESQ];
-static LAT_MULT: [f64; 5] = [LAT_F, LAT_SQ, LAT_SSQ, LAT_ESQ, LAT_SESQ];
+static LONG_MULT: [f64; 6] = [LONG_F, LONG_SQ, LONG_SSQ, LONG_ESQ, LONG_SESQ, LONG_HSQ];
+static LAT_MULT: [f64; 6] = [LAT_F, LAT_SQ, LAT_SSQ, LAT_ESQ, LAT_SESQ, LAT_HSQ];
 
-pub fn grid_to_longlat(grid: &str) -> Result<(f64, f64), MHError> {
-    // Validate alpha/digit format
-    // FIXME: Actual values should be A-R 0-9 a-x 0-9 A-X
-    let d = |a: char| a.is_ascii_digit();
-    let l = |a: char| a.is_ascii_alphabetic();
-    let checks = [l, l, d, d, l, l, d, d, l, l];
-    let check = grid
-        .chars()
-        .zip(checks)
-        .map(|(c, lmb)| lmb(c))
-        .collect::<Vec<bool>>();
+// The first position (and character) of a grid that isn't within the range
+// of that position: A-R 0-9 a-x 0-9 a-x 0-9 (case insensitive)
+fn invalid_position(grid: &str) -> Option<(usize, char)> {
+    grid.chars()
+        .take(12)
+        .enumerate()
+        .find(|&(i, c)| !CharClass::at(i).matches(c))
+}
 
-    // If any of them are false, we've got an invalid grid string
-    if check.iter().filter(|b| !*b).count() != 0 {
-        return Err(MHError::InvalidGrid(grid.to_string()));
+// Make sure a grid string is well formed: correct length, and each character
+// within the range of its position
+fn validate_grid(grid: &str) -> Result<(), MHError> {
+    if let Some((position, found)) = invalid_position(grid) {
+        return Err(MHError::InvalidGridChar {
+            grid: grid.to_string(),
+            position,
+            found,
+        });
     }
 
-    // Also make sure the length is even (and not 2)
+    // Also make sure the length is even
     match grid.len() {
-        4 | 6 | 8 | 10 => {}
+        2 | 4 | 6 | 8 | 10 | 12 => {}
         l => return Err(MHError::InvalidGridLength(l)),
     }
 
-    // Now it's just a matter of calculating the offsets from the grid
-    let vals: Vec<u32> = "AA00AA00AA"
+    Ok(())
+}
+
+// Whether a grid is valid, without allocating anything. This is faster than
+// checking if grid_to_longlat() returns an error, e.g. for filtering logs.
+pub fn is_valid_grid(grid: &str) -> bool {
+    matches!(grid.len(), 2 | 4 | 6 | 8 | 10 | 12) && invalid_position(grid).is_none()
+}
+
+// Canonical casing of each position, i.e. uppercase field, lowercase subsquare
+// and uppercase superextended square
+static CANONICAL: &str = "AA00aa00AA00";
+
+fn canonical_grid(grid: &str) -> String {
+    CANONICAL
         .chars()
         .zip(grid.chars())
-        .map(|(t, c)| (c.to_ascii_uppercase() as u32) - (t as u32))
+        .map(|(t, c)| {
+            if t.is_ascii_lowercase() {
+                c.to_ascii_lowercase()
+            } else {
+                c.to_ascii_uppercase()
+            }
+        })
+        .collect()
+}
+
+// Validate a grid and return it in its conventional casing, so "fm18LV" becomes
+// "FM18lv"
+pub fn normalize_grid(grid: &str) -> Result<String, MHError> {
+    validate_grid(grid)?;
+    Ok(canonical_grid(grid))
+}
+
+// Like normalize_grid(), but only accepts grids that are already in canonical
+// casing, i.e. "FM18lv" but not "FM18LV" or "fm18lv"
+pub fn parse_grid_strict(grid: &str) -> Result<String, MHError> {
+    let normalized = normalize_grid(grid)?;
+    if normalized != grid {
+        return Err(MHError::InvalidGridCase(grid.to_string()));
+    }
+    Ok(normalized)
+}
+
+// Like normalize_grid(), but first strips whitespace, hyphens and dots that
+// some logs use to group the pairs, e.g. "FM 18 lv" or "FM-18-LV"
+pub fn parse_grid_lenient(grid: &str) -> Result<String, MHError> {
+    let cleaned: String = grid
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '.')
         .collect();
+    normalize_grid(&cleaned)
+}
+
+// Like normalize_grid(), but drops the last character of a grid cut off in
+// the middle of a pair, e.g. "FM18l" is parsed as "FM18"
+pub fn parse_grid_truncating(grid: &str) -> Result<String, MHError> {
+    let mut chars = grid.chars();
+    let count = grid.chars().count();
+    if count % 2 == 1 && count > 1 {
+        chars.next_back();
+    }
+    normalize_grid(chars.as_str())
+}
+
+// Convert a grid to long/lat. Note that this returns the center of the grid,
+// see grid_center().
+pub fn grid_to_longlat(grid: &str) -> Result<(f64, f64), MHError> {
+    grid_center(grid)
+}
+
+// Same as grid_to_longlat(), but in (lat, long) order like most maps and GPS
+// units show coordinates
+pub fn grid_to_latlong(grid: &str) -> Result<(f64, f64), MHError> {
+    let (long, lat) = grid_to_longlat(grid)?;
+    Ok((lat, long))
+}
+
+// Convert a grid to the long/lat of its south west corner, without moving it
+// to the center like grid_to_longlat() does. See grid_southwest_corner().
+pub fn grid_to_corner(grid: &str) -> Result<(f64, f64), MHError> {
+    grid_southwest_corner(grid)
+}
+
+// The coordinate in the middle of the area covered by the grid. Converting
+// this back to a grid at the same precision always yields the same grid,
+// while the corners may round to a neighbor.
+pub fn grid_center(grid: &str) -> Result<(f64, f64), MHError> {
+    validate_grid(grid)?;
+    Ok(decode_grid(grid))
+}
+
+// The south west (lower left) corner of the grid, which is what the grid
+// string literally encodes. Most other Maidenhead implementations return this.
+pub fn grid_southwest_corner(grid: &str) -> Result<(f64, f64), MHError> {
+    validate_grid(grid)?;
+    Ok(decode_corner(grid))
+}
+
+// The offset of each character of an already validated grid within its range.
+// Validation makes sure no character is below the start of its range, but
+// saturate anyway so that a missed validation can't panic on underflow.
+fn grid_values(grid: &str) -> Vec<u32> {
+    "AA00AA00AA00"
+        .chars()
+        .zip(grid.chars())
+        .map(|(t, c)| (c.to_ascii_uppercase() as u32).saturating_sub(t as u32))
+        .collect()
+}
+
+// Calculate the south west corner of an already validated grid
+fn decode_corner(grid: &str) -> (f64, f64) {
+    // Now it's just a matter of calculating the offsets from the grid
+    let vals = grid_values(grid);
 
     // And multiplying each of them with their per-unit value
     let long: f64 = vals
@@ -88,234 +307,4698 @@ pub fn grid_to_longlat(grid: &str) -> Result<(f64, f64), MHError> {
         .map(|(&v, m)| v as f64 * m)
         .sum();
 
+    // Finally, adjust for origin offsets
+    (long - LONG_OFFSET, lat - LAT_OFFSET)
+}
+
+// Width and height in degrees of the cell covered by a grid of a (valid) length
+fn cell_dims(len: usize) -> (f64, f64) {
+    let idx = len / 2 - 1;
+    (LONG_MULT[idx], LAT_MULT[idx])
+}
+
+// Calculate the long/lat of an already validated grid
+fn decode_grid(grid: &str) -> (f64, f64) {
+    let (long, lat) = decode_corner(grid);
+
     // Move the returned value into the middle of the precision given.
     // This avoids imprecision due to rounding if doing grid->longlat->grid
     // (We do this in a unit testcase)
-    let idx = grid.len() / 2 - 1;
-    let long = long + LONG_MULT[idx] / 2.0;
-    let lat = lat + LAT_MULT[idx] / 2.0;
+    let (width, height) = cell_dims(grid.len());
+    (long + width / 2.0, lat + height / 2.0)
+}
 
-    // Finally, adjust for origin offsets
-    Ok((long - LONG_OFFSET, lat - LAT_OFFSET))
+// The center of a grid along with the size of the grid, i.e. the uncertainty
+// of the position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub long: f64,
+    pub lat: f64,
+    pub precision: usize,
+    pub cell_width_deg: f64,
+    pub cell_height_deg: f64,
 }
 
-pub fn longlat_to_grid(long: f64, lat: f64, precision: usize) -> Result<String, MHError> {
-    let charoff = |base: char, off: u32| std::char::from_u32(base as u32 + off);
+pub fn grid_to_longlat_detailed(grid: &str) -> Result<Location, MHError> {
+    let (long, lat) = grid_center(grid)?;
+    let (cell_width_deg, cell_height_deg) = cell_dims(grid.len());
+    Ok(Location {
+        long,
+        lat,
+        precision: grid.len(),
+        cell_width_deg,
+        cell_height_deg,
+    })
+}
+
+// The area covered by a grid, as (min_long, min_lat, max_long, max_lat)
+pub fn grid_bounds(grid: &str) -> Result<(f64, f64, f64, f64), MHError> {
+    validate_grid(grid)?;
+    let (long, lat) = decode_corner(grid);
+    let (width, height) = cell_dims(grid.len());
+    Ok((long, lat, long + width, lat + height))
+}
 
-    // It only makes sense to have 4+ even number of characters in a grid square
+// It only makes sense to have an even number of characters in a grid square
+fn validate_precision(precision: usize) -> Result<(), MHError> {
     match precision {
-        4 | 6 | 8 | 10 => {}
-        p => return Err(MHError::InvalidGridLength(p)),
+        2 | 4 | 6 | 8 | 10 | 12 => Ok(()),
+        p => Err(MHError::InvalidPrecision(p)),
     }
+}
 
-    if long > 180.0 || long < -180.0 || lat < -180.0 || lat > 180.0 {
+fn validate_longlat(long: f64, lat: f64) -> Result<(), MHError> {
+    // The range checks would catch NaN and infinity too, but be explicit
+    if !long.is_finite() || !lat.is_finite() {
         return Err(MHError::InvalidLongLat(long, lat));
     }
+    if !(-180.0..=180.0).contains(&long) || !(-90.0..=90.0).contains(&lat) {
+        return Err(MHError::InvalidLongLat(long, lat));
+    }
+    Ok(())
+}
+
+pub fn longlat_to_grid(long: f64, lat: f64, precision: usize) -> Result<String, MHError> {
+    validate_precision(precision)?;
+    validate_longlat(long, lat)?;
+
+    let mut buf = [0u8; 12];
+    encode_grid(long, lat, &mut buf[..precision]);
+    Ok(buf[..precision].iter().map(|&b| b as char).collect())
+}
+
+// Same as longlat_to_grid(), but taking the latitude first
+pub fn latlong_to_grid(lat: f64, long: f64, precision: usize) -> Result<String, MHError> {
+    longlat_to_grid(long, lat, precision)
+}
+
+// Same as longlat_to_grid(), but writes the grid to the start of a caller
+// provided buffer instead of allocating a String
+pub fn longlat_to_grid_buf(
+    long: f64,
+    lat: f64,
+    precision: usize,
+    buf: &mut [u8],
+) -> Result<&str, MHError> {
+    validate_precision(precision)?;
+    if buf.len() < precision {
+        return Err(MHError::BufferTooSmall(buf.len(), precision));
+    }
+
+    validate_longlat(long, lat)?;
+
+    let out = &mut buf[..precision];
+    encode_grid(long, lat, out);
+    core::str::from_utf8(out).map_err(|_| MHError::Unknown)
+}
+
+// Same as longlat_to_grid(), but with the precision known at compile time so
+// the per-precision work can be optimized away. N must be one of
+// 2/4/6/8/10/12, anything else fails to compile.
+pub fn longlat_to_grid_const<const N: usize>(long: f64, lat: f64) -> Result<String, MHError> {
+    const {
+        assert!(
+            matches!(N, 2 | 4 | 6 | 8 | 10 | 12),
+            "only 2/4/6/8/10/12 character grids supported"
+        )
+    };
+
+    validate_longlat(long, lat)?;
+
+    let mut buf = [0u8; N];
+    encode_grid(long, lat, &mut buf);
+    Ok(buf.iter().map(|&b| b as char).collect())
+}
+
+// The grids of every precision that a coordinate is in, from the 2 character
+// field down to the 12 character hyperextended square. Each one extends the
+// previous, so they're all cut from a single full precision encoding.
+pub fn grid_refinements(long: f64, lat: f64) -> Result<impl Iterator<Item = String>, MHError> {
+    validate_longlat(long, lat)?;
+
+    let mut buf = [0u8; 12];
+    encode_grid(long, lat, &mut buf);
+    Ok((2..=12)
+        .step_by(2)
+        .map(move |precision| buf[..precision].iter().map(|&b| b as char).collect()))
+}
+
+// Write the grid of an already validated coordinate to `out`, one ASCII
+// character per byte. The precision is the length of `out`.
+fn encode_grid(long: f64, lat: f64, out: &mut [u8]) {
+    let precision = out.len();
 
     // Do the math to calculate each position, per the w8bh website
     let long = long + LONG_OFFSET;
     let lat = lat + LAT_OFFSET;
-    let mut vals = Vec::new();
-    vals.push(long / LONG_F);
-    vals.push(lat / LAT_F);
-    vals.push(long % LONG_F / LONG_SQ);
-    vals.push(lat % LAT_F / LAT_SQ);
-    vals.push(long % LONG_SQ / LONG_SSQ);
-    vals.push(lat % LAT_SQ / LAT_SSQ);
-    vals.push(long % LONG_SSQ / LONG_ESQ);
-    vals.push(lat % LAT_SSQ / LAT_ESQ);
-    vals.push(long % LONG_ESQ / LONG_SESQ);
-    vals.push(lat % LAT_ESQ / LAT_SESQ);
-
-    vals.truncate(precision);
-
-    let grid: Option<String> = "AA00aa00AA"
-        .chars()
-        .zip(vals)
-        .map(|(b, o)| charoff(b, o as u32))
-        .collect();
-    match grid {
-        Some(g) => Ok(g),
-        None => Err(MHError::Unknown),
+    let mut vals = [
+        long / LONG_F,
+        lat / LAT_F,
+        long % LONG_F / LONG_SQ,
+        lat % LAT_F / LAT_SQ,
+        long % LONG_SQ / LONG_SSQ,
+        lat % LAT_SQ / LAT_SSQ,
+        long % LONG_SSQ / LONG_ESQ,
+        lat % LAT_SSQ / LAT_ESQ,
+        long % LONG_ESQ / LONG_SESQ,
+        lat % LAT_ESQ / LAT_SESQ,
+        long % LONG_SESQ / LONG_HSQ,
+        lat % LAT_SESQ / LAT_HSQ,
+    ];
+
+    // Exactly 180 longitude or 90 latitude end up one past the last field,
+    // but belong to the last cell. Saturate every position from the first
+    // one that's out of range, on that axis.
+    for axis in 0..2 {
+        let mut saturate = false;
+        for i in (axis..precision).step_by(2) {
+            let max = (LEVEL_CELLS[i / 2] - 1) as f64;
+            saturate |= vals[i] >= max + 1.0;
+            if saturate {
+                vals[i] = max;
+            }
+        }
+    }
+
+    for ((o, base), val) in out.iter_mut().zip(CANONICAL.bytes()).zip(vals) {
+        *o = base + val as u8;
     }
 }
 
-// Calculate the distance between two grids, using the haversine
-// formula:
-// a = sin²(Δφ/2) + cos φ1 ⋅ cos φ2 ⋅ sin²(Δλ/2)
-// c = 2 ⋅ atan2( √a, √(1−a) )
-// d = R ⋅ c
-// where:
-//  φ is latitude, λ is longitude, R is earth’s radius (mean radius = 6,371km);
-//  Bearing:
-//  θ = atan2( sin Δλ ⋅ cos φ2 , cos φ1 ⋅ sin φ2 − sin φ1 ⋅ cos φ2 ⋅ cos Δλ )
+// Number of cells along each axis that each level is divided into
+static LEVEL_CELLS: [u64; 6] = [18, 10, 24, 10, 24, 10];
 
-pub fn grid_dist_bearing(from: &str, to: &str) -> Result<(f64, f64), MHError> {
-    static RADIUS: f64 = 6371.0;
-    let (from_long, from_lat) = grid_to_longlat(from)?;
-    let (to_long, to_lat) = grid_to_longlat(to)?;
+// Number of cells along each axis of the world at a (valid) precision
+fn cells_per_axis(precision: usize) -> u64 {
+    LEVEL_CELLS[..precision / 2].iter().product()
+}
 
-    #[allow(non_snake_case)]
-    let Δλ = (to_long - from_long).to_radians();
-    #[allow(non_snake_case)]
-    let Δφ = (to_lat - from_lat).to_radians();
-    let φ1 = from_lat.to_radians();
-    let φ2 = to_lat.to_radians();
+// The column and row of an already validated grid, i.e. the index of the cell
+// counted from the -180/-90 corner among all cells of the same precision
+fn grid_to_cell(grid: &str) -> (u64, u64) {
+    let vals = grid_values(grid);
+    let index = |start: usize| {
+        vals.iter()
+            .skip(start)
+            .step_by(2)
+            .zip(LEVEL_CELLS)
+            .fold(0, |acc, (&v, n)| acc * n + v as u64)
+    };
+    (index(0), index(1))
+}
 
-    let a: f64 = (Δφ / 2.0).sin().powi(2) + φ1.cos() * φ2.cos() * (Δλ / 2.0).sin().powi(2);
-    let c: f64 = 2.0 * (a.sqrt()).atan2((1.0 - a).sqrt());
+// The grid at a (valid) column and row, see grid_to_cell()
+fn cell_to_grid(col: u64, row: u64, precision: usize) -> String {
+    let mut vals = vec![0; precision];
+    let (mut col, mut row) = (col, row);
+    for level in (0..precision / 2).rev() {
+        vals[level * 2] = col % LEVEL_CELLS[level];
+        vals[level * 2 + 1] = row % LEVEL_CELLS[level];
+        col /= LEVEL_CELLS[level];
+        row /= LEVEL_CELLS[level];
+    }
+    CANONICAL
+        .chars()
+        .zip(vals)
+        .filter_map(|(b, o)| core::char::from_u32(b as u32 + o as u32))
+        .collect()
+}
 
-    let dist = RADIUS * c;
-    let bearing = (Δλ.sin() * φ2.cos()).atan2(φ1.cos() * φ2.sin() - φ1.sin() * φ2.cos() * Δλ.cos());
-    let bearing = (bearing.to_degrees() + 360.0) % 360.0;
+// Move a grid by a number of cells along each axis at its own precision,
+// positive being east and north, wrapping around at the antimeridian. Moving
+// past a pole is an error. Any offset is accepted without overflowing, the
+// columns simply wrap around as many times as needed.
+pub fn step_grid(grid: &str, d_col: i64, d_row: i64) -> Result<String, MHError> {
+    validate_grid(grid)?;
+    let precision = grid.len();
+    let cells = cells_per_axis(precision) as i64;
+    let (col, row) = grid_to_cell(grid);
 
-    Ok((dist, bearing))
+    let row = (row as i64).checked_add(d_row).unwrap_or(-1);
+    if !(0..cells).contains(&row) {
+        let (long, lat) = decode_grid(grid);
+        let (width, height) = cell_dims(precision);
+        return Err(MHError::InvalidLongLat(
+            long + d_col as f64 * width,
+            lat + d_row as f64 * height,
+        ));
+    }
+    let col = (col as i64 + d_col.rem_euclid(cells)) % cells;
+
+    Ok(cell_to_grid(col as u64, row as u64, precision))
 }
 
-pub fn grid_distance(from: &str, to: &str) -> Result<f64, MHError> {
-    let (dist, _) = grid_dist_bearing(from, to)?;
-    Ok(dist)
+// The eight grids surrounding a grid, at the same precision, in the order
+// N, NE, E, SE, S, SW, W, NW. Grids at the antimeridian wrap around to the
+// other side, while grids next to a pole have no neighbors on that side and
+// return an error.
+pub fn grid_neighbors(grid: &str) -> Result<[String; 8], MHError> {
+    Ok([
+        step_grid(grid, 0, 1)?,
+        step_grid(grid, 1, 1)?,
+        step_grid(grid, 1, 0)?,
+        step_grid(grid, 1, -1)?,
+        step_grid(grid, 0, -1)?,
+        step_grid(grid, -1, -1)?,
+        step_grid(grid, -1, 0)?,
+        step_grid(grid, -1, 1)?,
+    ])
 }
 
-pub fn grid_bearing(from: &str, to: &str) -> Result<f64, MHError> {
-    let (_, bearing) = grid_dist_bearing(from, to)?;
-    Ok(bearing)
+// Whether two grids of the same precision share an edge or a corner, i.e.
+// whether one is among the neighbors of the other. Like grid_neighbors() this
+// wraps around the antimeridian, but a grid next to a pole simply has fewer
+// neighbors. A grid isn't adjacent to itself.
+pub fn grids_adjacent(a: &str, b: &str) -> Result<bool, MHError> {
+    let a = normalize_grid(a)?;
+    let b = normalize_grid(b)?;
+    if a.len() != b.len() {
+        return Err(MHError::PrecisionMismatch(a.len(), b.len()));
+    }
+
+    let steps = [
+        (0, 1),
+        (1, 1),
+        (1, 0),
+        (1, -1),
+        (0, -1),
+        (-1, -1),
+        (-1, 0),
+        (-1, 1),
+    ];
+    Ok(steps
+        .iter()
+        .filter_map(|&(d_col, d_row)| step_grid(&a, d_col, d_row).ok())
+        .any(|n| n == b))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// Whether the area of the inner grid is within the outer grid, e.g. "FM18lv"
+// is within "FM18". A grid contains itself, but never a coarser grid.
+pub fn grid_contains(outer: &str, inner: &str) -> Result<bool, MHError> {
+    let outer = normalize_grid(outer)?;
+    let inner = normalize_grid(inner)?;
+    Ok(inner.starts_with(&outer))
+}
 
-    // From https://stackoverflow.com/questions/30856285/assert-eq-with-floating-point-numbers-and-delta
-    macro_rules! assert_delta {
-        ($x:expr, $y:expr, $d:expr) => {
-            let x = $x as f64;
-            let y = $y as f64;
-            if !((x - y).abs() < $d || (y - x).abs() < $d) {
-                panic!();
-            }
-        };
+// All grids of the given precision that cover some part of the box, in row
+// major order from the south west corner. Edge cells that only partially
+// overlap the box are included, but not those that only touch its edge. The
+// box can't wrap around the antimeridian, so min_long must be less than
+// max_long (and min_lat less than max_lat) or nothing is returned.
+//
+// Grids are generated lazily, so memory use is constant, but the number of
+// grids grows quickly with precision: a 1x1 degree box holds 16.6 million
+// 10 character grids.
+pub fn grids_in_box(
+    min_long: f64,
+    min_lat: f64,
+    max_long: f64,
+    max_lat: f64,
+    precision: usize,
+) -> Result<impl Iterator<Item = String>, MHError> {
+    let (min_col, min_row) = grid_to_cell(&longlat_to_grid(min_long, min_lat, precision)?);
+    let max_grid = longlat_to_grid(max_long, max_lat, precision)?;
+    let (mut max_col, mut max_row) = grid_to_cell(&max_grid);
+
+    // A max edge exactly on a grid edge only touches the grid beyond it
+    let (corner_long, corner_lat) = decode_corner(&max_grid);
+    if corner_long == max_long && max_col > min_col {
+        max_col -= 1;
+    }
+    if corner_lat == max_lat && max_row > min_row {
+        max_row -= 1;
     }
 
-    // These values come out of the PDF referenced at the top of this file
-    static TEST_GRID: &str = &"FM18lv53SL";
-    static TEST_LONG: f64 = -77.035278;
-    static TEST_LAT: f64 = 38.889484;
+    Ok((min_row..=max_row)
+        .flat_map(move |row| (min_col..=max_col).map(move |col| cell_to_grid(col, row, precision))))
+}
 
-    fn precision_n(n: usize) {
-        let grid = longlat_to_grid(TEST_LONG, TEST_LAT, n).unwrap();
-        let mut check = String::from(TEST_GRID);
-        check.truncate(n);
-        println!("Grid ({}): {}", n, check);
-        assert_eq!(grid, check);
+// A validated grid locator, stored in canonical casing. Parse one with
+// `"FM18lv".parse::<Grid>()`. Since the casing is normalized, grids compare
+// and hash equal regardless of the casing they were parsed from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Grid {
+    grid: String,
+}
+
+impl Grid {
+    // The grid of the given precision that a coordinate is in
+    pub fn from_coords(long: f64, lat: f64, precision: usize) -> Result<Self, MHError> {
+        Ok(Grid {
+            grid: longlat_to_grid(long, lat, precision)?,
+        })
     }
 
-    #[test]
-    fn precision_10() {
-        precision_n(10);
+    pub fn as_str(&self) -> &str {
+        &self.grid
     }
 
-    #[test]
-    fn precision_8() {
-        precision_n(8);
+    // Number of characters in the locator, i.e. 2/4/6/8/10/12
+    pub fn precision(&self) -> usize {
+        self.grid.len()
     }
 
-    #[test]
-    fn precision_6() {
-        precision_n(6);
+    // Same as grid_to_longlat(), but the grid is known to be valid already
+    pub fn to_longlat(&self) -> (f64, f64) {
+        decode_grid(&self.grid)
     }
 
-    #[test]
-    fn precision_4() {
-        precision_n(4);
+    // Z-order (Morton) code of the south west corner of the grid, by
+    // interleaving the bits of its column and row at the finest precision
+    fn morton(&self) -> u64 {
+        let (col, row) = grid_to_cell(&self.grid);
+        let scale = cells_per_axis(12) / cells_per_axis(self.grid.len());
+        let spread = |mut v: u64| {
+            let mut out = 0;
+            for bit in 0..32 {
+                out |= (v & 1) << (2 * bit);
+                v >>= 1;
+            }
+            out
+        };
+        spread(col * scale) | spread(row * scale) << 1
     }
+}
 
-    #[test]
-    fn precision_inval() {
-        let grid = longlat_to_grid(TEST_LONG, TEST_LAT, 5);
-        assert!(grid.is_err());
+// Grids are ordered along a Z-order curve over the world rather than
+// alphabetically, so that grids near each other also tend to sort near each
+// other, e.g. for range queries on a BTreeMap. Grids that start at the same
+// south west corner, like "FM18" and "FM18aa", sort coarsest first.
+impl Ord for Grid {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.morton()
+            .cmp(&other.morton())
+            .then_with(|| self.grid.len().cmp(&other.grid.len()))
     }
+}
 
-    #[test]
-    fn precision_inval_lat() {
-        let grid = longlat_to_grid(TEST_LONG, 921.0, 10);
-        assert!(grid.is_err());
+impl PartialOrd for Grid {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
     }
+}
 
-    #[test]
-    fn precision_inval_long() {
-        let grid = longlat_to_grid(-201.0, TEST_LAT, 10);
-        assert!(grid.is_err());
+// The square at 0/0, "JJ00", also known as null island. It's just a
+// placeholder, e.g. for deriving Default, and not a meaningful location.
+impl Default for Grid {
+    fn default() -> Self {
+        Grid {
+            grid: "JJ00".to_string(),
+        }
     }
+}
 
-    fn longlat_n(n: usize) {
-        let mut grid_in = String::from(TEST_GRID);
-        grid_in.truncate(n);
+impl FromStr for Grid {
+    type Err = MHError;
 
-        let ll = grid_to_longlat(&grid_in.as_str());
-        assert!(!ll.is_err());
+    fn from_str(grid: &str) -> Result<Self, Self::Err> {
+        Ok(Grid {
+            grid: normalize_grid(grid)?,
+        })
+    }
+}
 
-        // Make sure it's within the margin of error of the smallest field
-        let (long, lat) = ll.unwrap();
-        assert_delta!(long, TEST_LONG, LONG_MULT[n / 2 - 1]);
-        assert_delta!(lat, TEST_LAT, LAT_MULT[n / 2 - 1]);
+// Convert a (long, lat) pair to a 6 character grid, the usual precision for
+// amateur radio
+impl TryFrom<(f64, f64)> for Grid {
+    type Error = MHError;
 
-        // Let's convert it back to grid and compare
-        let grid = longlat_to_grid(long, lat, n).unwrap();
-        assert_eq!(grid_in, grid);
+    fn try_from((long, lat): (f64, f64)) -> Result<Self, Self::Error> {
+        Grid::from_coords(long, lat, 6)
     }
+}
 
-    #[test]
-    fn longlat10() {
-        longlat_n(10);
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.grid)
     }
+}
 
-    #[test]
-    fn longlat8() {
-        longlat_n(8);
-    }
+// The corners of a grid as (long, lat) pairs, in the order south west, south
+// east, north east, north west. That is, counterclockwise starting at the
+// corner the grid is numbered from.
+pub fn grid_corners(grid: &str) -> Result<[(f64, f64); 4], MHError> {
+    let (min_long, min_lat, max_long, max_lat) = grid_bounds(grid)?;
+    Ok([
+        (min_long, min_lat),
+        (max_long, min_lat),
+        (max_long, max_lat),
+        (min_long, max_lat),
+    ])
+}
 
-    #[test]
-    fn longlat6() {
-        longlat_n(6);
+// Grids serialize as their canonical string, and are validated when
+// deserialized
+#[cfg(feature = "serde")]
+impl Serialize for Grid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.grid)
     }
+}
 
-    #[test]
-    fn longlat4() {
-        longlat_n(4);
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Grid {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let grid = String::deserialize(deserializer)?;
+        grid.parse().map_err(serde::de::Error::custom)
     }
+}
 
-    #[test]
-    fn longlat_invalid() {
-        let ret = grid_to_longlat("AI021");
-        assert!(ret.is_err());
-        let ret = grid_to_longlat("AIA2");
-        assert!(ret.is_err());
-        let ret = grid_to_longlat("🤷I00");
-        assert!(ret.is_err());
-        let ret = grid_to_longlat("AA00AA00AA00");
-        assert!(ret.is_err());
-        let ret = grid_to_longlat("AA00AA00AA");
-        assert!(!ret.is_err());
+// A position parsed from user input, which can be either a grid locator or a
+// "lat,long" pair of decimal degrees. A grid resolves to its center.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatLong {
+    pub lat: f64,
+    pub long: f64,
+}
+
+impl LatLong {
+    pub fn to_grid(&self, precision: usize) -> Result<String, MHError> {
+        longlat_to_grid(self.long, self.lat, precision)
     }
+}
 
-    #[test]
-    fn test_distance_null() {
-        let dist = grid_distance(TEST_GRID, TEST_GRID).unwrap();
-        assert_eq!(dist, 0.0);
+impl FromStr for LatLong {
+    type Err = MHError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok((long, lat)) = grid_to_longlat(s) {
+            return Ok(LatLong { lat, long });
+        }
+
+        let (lat, long) = s
+            .split_once(',')
+            .and_then(|(lat, long)| Some((lat.trim().parse().ok()?, long.trim().parse().ok()?)))
+            .ok_or_else(|| MHError::InvalidGrid(s.to_string()))?;
+        validate_longlat(long, lat)?;
+        Ok(LatLong { lat, long })
     }
+}
 
-    #[test]
-    fn test_distance_home() {
-        let dist = grid_distance("CM87um", "KP04ow").unwrap();
-        let bear = grid_bearing("CM87um", "KP04ow").unwrap();
-        println!("Distance: {} Bearing: {}", dist, bear);
-        println!(
-            "from: {:?} To: {:?}",
-            grid_to_longlat("CM87um"),
-            grid_to_longlat("KP04ow")
-        );
-        assert_delta!(dist, 8189.0, 1.0);
-        assert_delta!(bear, 15.224, 0.001);
+// Calculate the distance between two grids, using the haversine
+// formula:
+// a = sin²(Δφ/2) + cos φ1 ⋅ cos φ2 ⋅ sin²(Δλ/2)
+// c = 2 ⋅ atan2( √a, √(1−a) )
+// d = R ⋅ c
+// where:
+//  φ is latitude, λ is longitude, R is earth’s radius (mean radius = 6,371km);
+//  Bearing:
+//  θ = atan2( sin Δλ ⋅ cos φ2 , cos φ1 ⋅ sin φ2 − sin φ1 ⋅ cos φ2 ⋅ cos Δλ )
+
+// The central angle in radians between two (long, lat) coordinates, per the
+// haversine formula above
+fn angular_distance(from: (f64, f64), to: (f64, f64)) -> f64 {
+    #[allow(non_snake_case)]
+    let Δλ = (to.0 - from.0).to_radians();
+    #[allow(non_snake_case)]
+    let Δφ = (to.1 - from.1).to_radians();
+    let φ1 = from.1.to_radians();
+    let φ2 = to.1.to_radians();
+
+    let a: f64 = (Δφ / 2.0).sin().powi(2) + φ1.cos() * φ2.cos() * (Δλ / 2.0).sin().powi(2);
+    // Rounding can push a just above 1 for antipodal points
+    let a = a.min(1.0);
+    2.0 * (a.sqrt()).atan2((1.0 - a).sqrt())
+}
+
+// The initial bearing in degrees (0-360) from one (long, lat) coordinate to
+// another, per the formula above
+fn initial_bearing(from: (f64, f64), to: (f64, f64)) -> f64 {
+    #[allow(non_snake_case)]
+    let Δλ = (to.0 - from.0).to_radians();
+    let φ1 = from.1.to_radians();
+    let φ2 = to.1.to_radians();
+
+    let bearing = (Δλ.sin() * φ2.cos()).atan2(φ1.cos() * φ2.sin() - φ1.sin() * φ2.cos() * Δλ.cos());
+    (bearing.to_degrees() + 360.0) % 360.0
+}
+
+// Mean earth radius in km
+static RADIUS: f64 = 6371.0;
+
+// Distance in km between two (long, lat) coordinates, for when there's no
+// need to go through grids
+pub fn coord_distance(from: (f64, f64), to: (f64, f64)) -> Result<f64, MHError> {
+    validate_longlat(from.0, from.1)?;
+    validate_longlat(to.0, to.1)?;
+    Ok(RADIUS * angular_distance(from, to))
+}
+
+// Initial bearing in degrees between two (long, lat) coordinates
+pub fn coord_bearing(from: (f64, f64), to: (f64, f64)) -> Result<f64, MHError> {
+    validate_longlat(from.0, from.1)?;
+    validate_longlat(to.0, to.1)?;
+    Ok(initial_bearing(from, to))
+}
+
+// Both the distance and initial bearing between two (long, lat) coordinates,
+// same as grid_dist_bearing() for grids
+pub fn coord_dist_bearing(from: (f64, f64), to: (f64, f64)) -> Result<(f64, f64), MHError> {
+    validate_longlat(from.0, from.1)?;
+    validate_longlat(to.0, to.1)?;
+    Ok((
+        RADIUS * angular_distance(from, to),
+        initial_bearing(from, to),
+    ))
+}
+
+pub fn grid_dist_bearing(from: &str, to: &str) -> Result<(f64, f64), MHError> {
+    let (dist, bearing, _) = grid_dist_bearing_full(from, to)?;
+    Ok((dist, bearing))
+}
+
+// Distance, bearing, and the bearing back from `to` to `from`. On a sphere
+// the reverse bearing generally isn't the forward bearing + 180.
+pub fn grid_dist_bearing_full(from: &str, to: &str) -> Result<(f64, f64, f64), MHError> {
+    dist_bearing_with_radius(from, to, RADIUS)
+}
+
+// Distance and bearing along the long path, the other way around the great
+// circle. The long path leaves in the opposite direction of the short path
+// and covers the rest of the circumference.
+pub fn grid_long_path(from: &str, to: &str) -> Result<(f64, f64), MHError> {
+    let (dist, bearing) = grid_dist_bearing(from, to)?;
+    let circumference = 2.0 * core::f64::consts::PI * RADIUS;
+    Ok((circumference - dist, (bearing + 180.0) % 360.0))
+}
+
+// Distance and bearing along the rhumb line from one grid to another, i.e.
+// holding the same compass bearing all the way, which is how a course is
+// plotted as a straight line on a Mercator chart. The rhumb line is never
+// shorter than the great circle. The two are the same along the equator
+// and a meridian, and the difference grows with the latitude and with the
+// east-west span of the path.
+pub fn grid_rhumb_distance(from: &str, to: &str) -> Result<f64, MHError> {
+    let (dist, _) = rhumb_dist_bearing(grid_to_longlat(from)?, grid_to_longlat(to)?);
+    Ok(dist)
+}
+
+// The constant bearing in degrees (0-360) along the rhumb line, see
+// grid_rhumb_distance()
+pub fn grid_rhumb_bearing(from: &str, to: &str) -> Result<f64, MHError> {
+    let (_, bearing) = rhumb_dist_bearing(grid_to_longlat(from)?, grid_to_longlat(to)?);
+    Ok(bearing)
+}
+
+fn rhumb_dist_bearing(from: (f64, f64), to: (f64, f64)) -> (f64, f64) {
+    let φ1 = from.1.to_radians();
+    let φ2 = to.1.to_radians();
+    #[allow(non_snake_case)]
+    let Δφ = φ2 - φ1;
+    // The short way around, across the antimeridian if need be
+    #[allow(non_snake_case)]
+    let Δλ = ((to.0 - from.0 + 540.0) % 360.0 - 180.0).to_radians();
+
+    // The difference in latitude on a Mercator projection
+    let quarter = core::f64::consts::FRAC_PI_4;
+    #[allow(non_snake_case)]
+    let Δψ = ((quarter + φ2 / 2.0).tan() / (quarter + φ1 / 2.0).tan()).ln();
+    // Along a parallel Δφ/Δψ is 0/0, but goes toward cos(φ)
+    let q = if Δψ.abs() > 1e-12 {
+        Δφ / Δψ
+    } else {
+        φ1.cos()
+    };
+
+    let dist = RADIUS * (Δφ.powi(2) + (q * Δλ).powi(2)).sqrt();
+    let bearing = Δλ.atan2(Δψ);
+    (dist, (bearing.to_degrees() + 360.0) % 360.0)
+}
+
+fn dist_bearing_with_radius(from: &str, to: &str, radius: f64) -> Result<(f64, f64, f64), MHError> {
+    let from = grid_to_longlat(from)?;
+    let to = grid_to_longlat(to)?;
+
+    let dist = radius * angular_distance(from, to);
+    let bearing = initial_bearing(from, to);
+    let reverse = initial_bearing(to, from);
+
+    Ok((dist, bearing, reverse))
+}
+
+// Distance between two grid centers on the WGS84 ellipsoid, using Vincenty's
+// inverse formula. This is accurate to within a millimeter, where the
+// spherical grid_distance() can be off by up to 0.5%. The iteration doesn't
+// converge for nearly antipodal points, in which case this falls back to the
+// spherical distance.
+pub fn grid_distance_vincenty(from: &str, to: &str) -> Result<f64, MHError> {
+    Ok(grid_dist_bearing_vincenty(from, to)?.0)
+}
+
+// Initial bearing between two grid centers on the WGS84 ellipsoid. Nearly
+// antipodal points fall back to the spherical bearing, like the distance.
+pub fn grid_bearing_vincenty(from: &str, to: &str) -> Result<f64, MHError> {
+    Ok(grid_dist_bearing_vincenty(from, to)?.1)
+}
+
+// Both the distance and initial bearing on the WGS84 ellipsoid
+pub fn grid_dist_bearing_vincenty(from: &str, to: &str) -> Result<(f64, f64), MHError> {
+    let from = grid_to_longlat(from)?;
+    let to = grid_to_longlat(to)?;
+    Ok(vincenty_inverse(from, to).unwrap_or_else(|| {
+        (
+            RADIUS * angular_distance(from, to),
+            initial_bearing(from, to),
+        )
+    }))
+}
+
+// WGS84 ellipsoid semi-major axis (in km) and flattening
+static WGS84_A: f64 = 6378.137;
+static WGS84_F: f64 = 1.0 / 298.257223563;
+
+// Vincenty's inverse formula, see https://en.wikipedia.org/wiki/Vincenty%27s_formulae
+// Returns the distance in km and initial bearing in degrees (0-360), or None
+// if it fails to converge.
+fn vincenty_inverse(from: (f64, f64), to: (f64, f64)) -> Option<(f64, f64)> {
+    let (a, f) = (WGS84_A, WGS84_F);
+    let b = (1.0 - f) * a;
+
+    // Difference in longitude, normalized to -180..180 across the antimeridian
+    #[allow(non_snake_case)]
+    let L = ((to.0 - from.0 + 540.0) % 360.0 - 180.0).to_radians();
+    let u1 = ((1.0 - f) * from.1.to_radians().tan()).atan();
+    let u2 = ((1.0 - f) * to.1.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = (u1.sin(), u1.cos());
+    let (sin_u2, cos_u2) = (u2.sin(), u2.cos());
+
+    let mut λ = L;
+    for _ in 0..200 {
+        let (sin_λ, cos_λ) = (λ.sin(), λ.cos());
+        let sin_sigma =
+            ((cos_u2 * sin_λ).powi(2) + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_λ).powi(2)).sqrt();
+        if sin_sigma == 0.0 {
+            // Same point
+            return Some((0.0, 0.0));
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_λ;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_α = cos_u1 * cos_u2 * sin_λ / sin_sigma;
+        let cos2_α = 1.0 - sin_α.powi(2);
+        // On the equator cos²α is zero
+        let cos_2sigma_m = if cos2_α != 0.0 {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos2_α
+        } else {
+            0.0
+        };
+        let c = f / 16.0 * cos2_α * (4.0 + f * (4.0 - 3.0 * cos2_α));
+
+        let prev = λ;
+        λ = L
+            + (1.0 - c)
+                * f
+                * sin_α
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))));
+        if λ.abs() > core::f64::consts::PI {
+            break;
+        }
+        if (λ - prev).abs() < 1e-12 {
+            let u_sq = cos2_α * (a * a - b * b) / (b * b);
+            #[allow(non_snake_case)]
+            let A =
+                1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+            #[allow(non_snake_case)]
+            let B = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+            let delta_sigma = B
+                * sin_sigma
+                * (cos_2sigma_m
+                    + B / 4.0
+                        * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))
+                            - B / 6.0
+                                * cos_2sigma_m
+                                * (-3.0 + 4.0 * sin_sigma.powi(2))
+                                * (-3.0 + 4.0 * cos_2sigma_m.powi(2))));
+            let bearing = (cos_u2 * λ.sin()).atan2(cos_u1 * sin_u2 - sin_u1 * cos_u2 * λ.cos());
+            return Some((
+                b * A * (sigma - delta_sigma),
+                (bearing.to_degrees() + 360.0) % 360.0,
+            ));
+        }
+    }
+
+    None
+}
+
+// Points along the great circle path between the centers of two grids,
+// interpolated by spherical linear interpolation (slerp):
+// a = sin((1−f)⋅δ) / sin δ
+// b = sin(f⋅δ) / sin δ
+// x = a ⋅ cos φ1 ⋅ cos λ1 + b ⋅ cos φ2 ⋅ cos λ2
+// y = a ⋅ cos φ1 ⋅ sin λ1 + b ⋅ cos φ2 ⋅ sin λ2
+// z = a ⋅ sin φ1 + b ⋅ sin φ2
+// φ = atan2(z, √(x² + y²))
+// λ = atan2(y, x)
+// where δ is the angular distance and f the fraction along the path.
+//
+// Returns segments + 1 (long, lat) pairs, starting and ending at the grid
+// centers (so with zero segments only the start is returned). Antipodal grids
+// have no unique great circle between them, and returns an error.
+pub fn great_circle_path(
+    from: &str,
+    to: &str,
+    segments: usize,
+) -> Result<Vec<(f64, f64)>, MHError> {
+    let start = grid_to_longlat(from)?;
+    let end = grid_to_longlat(to)?;
+
+    let δ = angular_distance(start, end);
+    if (core::f64::consts::PI - δ).abs() < 1e-9 {
+        return Err(MHError::AntipodalGrids(from.to_string(), to.to_string()));
+    }
+
+    let mut path = vec![start];
+    for i in 1..segments {
+        path.push(slerp(start, end, δ, i as f64 / segments as f64));
+    }
+    if segments > 0 {
+        path.push(end);
+    }
+
+    Ok(path)
+}
+
+// The point at fraction f along the great circle between two (long, lat)
+// coordinates δ radians apart, per the formula above
+fn slerp(start: (f64, f64), end: (f64, f64), δ: f64, f: f64) -> (f64, f64) {
+    // Avoid dividing by sin δ for the same point
+    if δ == 0.0 {
+        return start;
+    }
+
+    let (λ1, φ1) = (start.0.to_radians(), start.1.to_radians());
+    let (λ2, φ2) = (end.0.to_radians(), end.1.to_radians());
+
+    let a = ((1.0 - f) * δ).sin() / δ.sin();
+    let b = (f * δ).sin() / δ.sin();
+    let x = a * φ1.cos() * λ1.cos() + b * φ2.cos() * λ2.cos();
+    let y = a * φ1.cos() * λ1.sin() + b * φ2.cos() * λ2.sin();
+    let z = a * φ1.sin() + b * φ2.sin();
+    let φ = z.atan2((x * x + y * y).sqrt());
+    let λ = y.atan2(x);
+    (λ.to_degrees(), φ.to_degrees())
+}
+
+// The grid at fraction t (0 to 1) of the way along the great circle path
+// between two grid centers, so 0 is the start and 1 the end grid
+pub fn grid_at_fraction(from: &str, to: &str, t: f64, precision: usize) -> Result<String, MHError> {
+    if !(0.0..=1.0).contains(&t) {
+        return Err(MHError::InvalidFraction(t));
+    }
+
+    let start = grid_to_longlat(from)?;
+    let end = grid_to_longlat(to)?;
+
+    let δ = angular_distance(start, end);
+    if (core::f64::consts::PI - δ).abs() < 1e-9 {
+        return Err(MHError::AntipodalGrids(from.to_string(), to.to_string()));
+    }
+
+    let (long, lat) = slerp(start, end, δ, t);
+    longlat_to_grid(long, lat, precision)
+}
+
+// Every grid of the given precision that the great circle path between two
+// grid centers passes through, in the order they're entered. Writing the path
+// as p(s) = a ⋅ cos s + b ⋅ sin s, where a is the start and b the unit vector
+// perpendicular to it toward the end, the points where it crosses a
+// meridian or a parallel can be solved for directly. Walking through those
+// crossings in order visits every grid the path enters, however narrow, e.g.
+// close to a pole, without sampling the path.
+pub fn grids_along_path(from: &str, to: &str, precision: usize) -> Result<Vec<String>, MHError> {
+    use core::f64::consts::{PI, TAU};
+
+    validate_precision(precision)?;
+    let start = grid_to_longlat(from)?;
+    let end = grid_to_longlat(to)?;
+
+    let δ = angular_distance(start, end);
+    if (PI - δ).abs() < 1e-9 {
+        return Err(MHError::AntipodalGrids(from.to_string(), to.to_string()));
+    }
+    if δ == 0.0 {
+        return Ok(vec![longlat_to_grid(start.0, start.1, precision)?]);
+    }
+
+    // A grid center is within a grid of the same or a coarser precision, but
+    // on the corner of finer grids, and the path only starts in one of those
+    let mut grids = Vec::new();
+    if precision <= from.len() {
+        grids.push(canonical_grid(&from[..precision]));
+    }
+
+    let unit = |(long, lat): (f64, f64)| {
+        let (λ, φ) = (long.to_radians(), lat.to_radians());
+        [φ.cos() * λ.cos(), φ.cos() * λ.sin(), φ.sin()]
+    };
+    let a = unit(start);
+    let e = unit(end);
+    let b: [f64; 3] = core::array::from_fn(|i| (e[i] - a[i] * δ.cos()) / δ.sin());
+    let point = move |s: f64| {
+        let p: [f64; 3] = core::array::from_fn(|i| a[i] * s.cos() + b[i] * s.sin());
+        let long = p[1].atan2(p[0]).to_degrees();
+        let lat = p[2].atan2((p[0] * p[0] + p[1] * p[1]).sqrt()).to_degrees();
+        (long, lat)
+    };
+    // Angles along the path into -π..π, so that a crossing just before the
+    // start stays before it
+    let wrap = |s: f64| {
+        let s = (s + PI) % TAU;
+        if s < 0.0 {
+            s + PI
+        } else {
+            s - PI
+        }
+    };
+
+    let (width, height) = cell_dims(precision);
+    let cells = cells_per_axis(precision) as i64;
+    let col = |long: f64| (((long + 180.0) / width).floor() as i64).clamp(0, cells - 1);
+    let row = |lat: f64| (((lat + 90.0) / height).floor() as i64).clamp(0, cells - 1);
+
+    // The longitude changes monotonically, east if positive, except along a
+    // meridian where it doesn't change at all, other than flipping over a pole
+    let east = a[0] * b[1] - a[1] * b[0];
+    let (start_col, end_col) = (col(start.0), col(end.0));
+    let meridians = match east {
+        c if c.abs() < 1e-12 => 0,
+        c if c > 0.0 => (end_col - start_col).rem_euclid(cells),
+        _ => (start_col - end_col).rem_euclid(cells),
+    };
+    let mut meridians = (1..=meridians)
+        .map(move |i| {
+            // The east edge of each grid going east, the west edge going west
+            let k = if east > 0.0 {
+                start_col + i
+            } else {
+                start_col - i + 1
+            };
+            let λ = (k.rem_euclid(cells) as f64 * width - 180.0).to_radians();
+            let (sin, cos) = (λ.sin(), λ.cos());
+            // The path crosses the plane of the meridian twice, at s and s + π,
+            // and only one of them is on the meridian rather than opposite it
+            let s = (sin * a[0] - cos * a[1]).atan2(cos * b[1] - sin * b[0]);
+            let (x, y) = (
+                a[0] * s.cos() + b[0] * s.sin(),
+                a[1] * s.cos() + b[1] * s.sin(),
+            );
+            wrap(if cos * x + sin * y < 0.0 { s + PI } else { s })
+        })
+        .peekable();
+
+    // The latitude is r ⋅ cos(s − ψ), so it goes through at most one extreme
+    // along the path, before and after which it's monotonic
+    let r = (a[2] * a[2] + b[2] * b[2]).sqrt();
+    let ψ = wrap(b[2].atan2(a[2]));
+    let extreme = [ψ, wrap(ψ + PI)]
+        .into_iter()
+        .find(|&s| r > 0.0 && s > 0.0 && s < δ);
+    let parallels = move |from_s: f64, to_s: f64| {
+        let (from_row, to_row) = (row(point(from_s).1), row(point(to_s).1));
+        let rising = to_row > from_row;
+        (1..=(to_row - from_row).abs()).map(move |i| {
+            let k = if rising {
+                from_row + i
+            } else {
+                from_row - i + 1
+            };
+            let angle = ((k as f64 * height - 90.0).to_radians().sin() / r)
+                .clamp(-1.0, 1.0)
+                .acos();
+            wrap(if rising { ψ - angle } else { ψ + angle })
+        })
+    };
+    let split = extreme.unwrap_or(δ);
+    let mut parallels = parallels(0.0, split)
+        .chain(extreme)
+        .chain(parallels(split, δ))
+        .peekable();
+
+    let crossings = core::iter::from_fn(|| match (meridians.peek(), parallels.peek()) {
+        (Some(m), Some(p)) if m <= p => meridians.next(),
+        (Some(_), None) => meridians.next(),
+        _ => parallels.next(),
+    });
+
+    // Each grid is then the one in the middle between two crossings
+    let mut prev = 0.0;
+    for s in crossings.chain(core::iter::once(δ)) {
+        let s = s.min(δ);
+        if s > prev {
+            let (long, lat) = point((prev + s) / 2.0);
+            let grid = longlat_to_grid(long, lat, precision)?;
+            if grids.last() != Some(&grid) {
+                grids.push(grid);
+            }
+            prev = s;
+        }
+    }
+
+    if precision <= to.len() {
+        let grid = canonical_grid(&to[..precision]);
+        if grids.last() != Some(&grid) {
+            grids.push(grid);
+        }
+    }
+
+    Ok(grids)
+}
+
+// The grid at the great circle midpoint between the centers of two grids:
+// Bx = cos φ2 ⋅ cos Δλ
+// By = cos φ2 ⋅ sin Δλ
+// φm = atan2( sin φ1 + sin φ2, √((cos φ1 + Bx)² + By²) )
+// λm = λ1 + atan2( By, cos φ1 + Bx )
+// Antipodal grids have no unique midpoint and return an error, like
+// great_circle_path().
+pub fn grid_midpoint(from: &str, to: &str, precision: usize) -> Result<String, MHError> {
+    let (from_long, from_lat) = grid_to_longlat(from)?;
+    let (to_long, to_lat) = grid_to_longlat(to)?;
+    let δ = angular_distance((from_long, from_lat), (to_long, to_lat));
+    if (core::f64::consts::PI - δ).abs() < 1e-9 {
+        return Err(MHError::AntipodalGrids(from.to_string(), to.to_string()));
+    }
+
+    #[allow(non_snake_case)]
+    let Δλ = (to_long - from_long).to_radians();
+    let φ1 = from_lat.to_radians();
+    let φ2 = to_lat.to_radians();
+    let λ1 = from_long.to_radians();
+
+    let bx = φ2.cos() * Δλ.cos();
+    let by = φ2.cos() * Δλ.sin();
+    let φm = (φ1.sin() + φ2.sin()).atan2(((φ1.cos() + bx).powi(2) + by.powi(2)).sqrt());
+    let λm = λ1 + by.atan2(φ1.cos() + bx);
+
+    // Normalize the longitude back into -180..180
+    let long = (λm.to_degrees() + 540.0) % 360.0 - 180.0;
+    longlat_to_grid(long, φm.to_degrees(), precision)
+}
+
+// The grid a given distance (in km) away from the center of a grid, along a
+// given initial bearing. This is the inverse of grid_dist_bearing():
+// φ2 = asin( sin φ1 ⋅ cos δ + cos φ1 ⋅ sin δ ⋅ cos θ )
+// λ2 = λ1 + atan2( sin θ ⋅ sin δ ⋅ cos φ1, cos δ − sin φ1 ⋅ sin φ2 )
+// where δ is the angular distance d/R and θ the bearing.
+pub fn destination_grid(
+    from: &str,
+    distance_km: f64,
+    bearing_deg: f64,
+    precision: usize,
+) -> Result<String, MHError> {
+    let (long, lat) = grid_to_longlat(from)?;
+
+    let δ = distance_km / RADIUS;
+    let θ = bearing_deg.to_radians();
+    let φ1 = lat.to_radians();
+    let λ1 = long.to_radians();
+
+    let φ2 = (φ1.sin() * δ.cos() + φ1.cos() * δ.sin() * θ.cos()).asin();
+    let λ2 = λ1 + (θ.sin() * δ.sin() * φ1.cos()).atan2(δ.cos() - φ1.sin() * φ2.sin());
+
+    // Normalize the longitude back into -180..180
+    let long = (λ2.to_degrees() + 540.0) % 360.0 - 180.0;
+    longlat_to_grid(long, φ2.to_degrees(), precision)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceUnit {
+    Kilometers,
+    Miles,
+    NauticalMiles,
+}
+
+impl DistanceUnit {
+    // Convert a distance in kilometers to this unit
+    fn convert_km(self, km: f64) -> f64 {
+        match self {
+            DistanceUnit::Kilometers => km,
+            DistanceUnit::Miles => km / 1.609344,
+            DistanceUnit::NauticalMiles => km / 1.852,
+        }
+    }
+}
+
+pub fn grid_distance(from: &str, to: &str) -> Result<f64, MHError> {
+    grid_distance_in(from, to, DistanceUnit::Kilometers)
+}
+
+pub fn grid_distance_in(from: &str, to: &str, unit: DistanceUnit) -> Result<f64, MHError> {
+    let (dist, _) = grid_dist_bearing(from, to)?;
+    Ok(unit.convert_km(dist))
+}
+
+// Distance on a sphere with the given radius in km, for matching tools that
+// use another earth radius (e.g. the equatorial 6378.137 km), or other bodies
+pub fn grid_distance_with_radius(from: &str, to: &str, radius_km: f64) -> Result<f64, MHError> {
+    let (dist, _, _) = dist_bearing_with_radius(from, to, radius_km)?;
+    Ok(dist)
+}
+
+pub fn grid_bearing(from: &str, to: &str) -> Result<f64, MHError> {
+    let (_, bearing) = grid_dist_bearing(from, to)?;
+    Ok(bearing)
+}
+
+// Same as grid_bearing(), but in the range (-180, 180] instead of 0-360.
+// Positive is clockwise from north, i.e. toward the east, and negative is
+// toward the west.
+pub fn grid_bearing_signed(from: &str, to: &str) -> Result<f64, MHError> {
+    let bearing = grid_bearing(from, to)?;
+    Ok(if bearing > 180.0 {
+        bearing - 360.0
+    } else {
+        bearing
+    })
+}
+
+pub fn grid_reverse_bearing(from: &str, to: &str) -> Result<f64, MHError> {
+    let (_, _, reverse) = grid_dist_bearing_full(from, to)?;
+    Ok(reverse)
+}
+
+// Parse one degrees/minutes/seconds coordinate, e.g. 38°53'22.1"N, 38 53 22.1 N,
+// 38:53:22.1N, N38°53.368' or -38.889. The components can be separated by any
+// of °'"′″: or whitespace, and the hemisphere is given either by a marker at the
+// start or end, or by a leading minus sign.
+fn parse_dms(dms: &str, positive: char, negative: char, max: f64) -> Result<f64, MHError> {
+    let err = || MHError::InvalidDms(dms.to_string());
+
+    let mut value = dms.trim();
+    let mut hemisphere = None;
+    if let Some(c) = value.chars().next_back().filter(char::is_ascii_alphabetic) {
+        hemisphere = Some(c.to_ascii_uppercase());
+        value = &value[..value.len() - 1];
+    } else if let Some(c) = value.chars().next().filter(char::is_ascii_alphabetic) {
+        hemisphere = Some(c.to_ascii_uppercase());
+        value = &value[1..];
+    }
+    let value = value.trim();
+    let (minus, value) = match value.strip_prefix('-') {
+        Some(v) => (true, v),
+        None => (false, value),
+    };
+    let sign = match (hemisphere, minus) {
+        (None, false) => 1.0,
+        (None, true) => -1.0,
+        (Some(h), false) if h == positive => 1.0,
+        (Some(h), false) if h == negative => -1.0,
+        _ => return Err(err()),
+    };
+
+    let parts = value
+        .split(|c: char| c.is_whitespace() || "°'\"′″:".contains(c))
+        .filter(|p| !p.is_empty())
+        .map(
+            |p| match p.chars().all(|c| c.is_ascii_digit() || c == '.') {
+                true => p.parse::<f64>().map_err(|_| err()),
+                false => Err(err()),
+            },
+        )
+        .collect::<Result<Vec<f64>, MHError>>()?;
+    let (deg, min, sec) = match parts[..] {
+        [d] => (d, 0.0, 0.0),
+        [d, m] => (d, m, 0.0),
+        [d, m, s] => (d, m, s),
+        _ => return Err(err()),
+    };
+    if min >= 60.0 || sec >= 60.0 {
+        return Err(err());
+    }
+
+    let value = deg + min / 60.0 + sec / 3600.0;
+    if value > max {
+        return Err(err());
+    }
+    Ok(sign * value)
+}
+
+// Convert degrees/minutes/seconds strings to long/lat, see parse_dms() for
+// the accepted formats. The longitude takes E/W and the latitude N/S markers.
+pub fn dms_to_longlat(long_dms: &str, lat_dms: &str) -> Result<(f64, f64), MHError> {
+    let long = parse_dms(long_dms, 'E', 'W', 180.0)?;
+    let lat = parse_dms(lat_dms, 'N', 'S', 90.0)?;
+    Ok((long, lat))
+}
+
+fn format_dms(value: f64, positive: char, negative: char) -> String {
+    // Round to tenths of seconds first, so that 59.96" carries over into
+    // the minutes instead of printing as 60.0"
+    let tenths = (value.abs() * 36000.0).round() as u64;
+    let hemisphere = if value < 0.0 && tenths > 0 {
+        negative
+    } else {
+        positive
+    };
+    format!(
+        "{}°{:02}'{:02}.{}\"{}",
+        tenths / 36000,
+        tenths / 600 % 60,
+        tenths / 10 % 60,
+        tenths % 10,
+        hemisphere
+    )
+}
+
+// Format long/lat as degrees/minutes/seconds, e.g. (77°02'07.0"W, 38°53'22.1"N)
+pub fn longlat_to_dms(long: f64, lat: f64) -> (String, String) {
+    (format_dms(long, 'E', 'W'), format_dms(lat, 'N', 'S'))
+}
+
+fn format_decimal(value: f64, positive: char, negative: char) -> String {
+    // Same as format_dms(), round first so tiny negative values don't end up
+    // as 0.0000 in the negative hemisphere
+    let units = (value.abs() * 10000.0).round() as u64;
+    let hemisphere = if value < 0.0 && units > 0 {
+        negative
+    } else {
+        positive
+    };
+    format!("{}.{:04}°{}", units / 10000, units % 10000, hemisphere)
+}
+
+// Format long/lat as decimal degrees with hemispheres, latitude first, e.g.
+// "38.8895°N, 77.0353°W"
+pub fn format_latlong(long: f64, lat: f64) -> String {
+    format!(
+        "{}, {}",
+        format_decimal(lat, 'N', 'S'),
+        format_decimal(long, 'E', 'W')
+    )
+}
+
+// The supported grid precisions, i.e. grid lengths
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GridPrecision {
+    Field = 2,
+    Square = 4,
+    Subsquare = 6,
+    Extended = 8,
+    SuperExtended = 10,
+    HyperExtended = 12,
+}
+
+impl TryFrom<usize> for GridPrecision {
+    type Error = MHError;
+
+    fn try_from(precision: usize) -> Result<Self, Self::Error> {
+        match precision {
+            2 => Ok(GridPrecision::Field),
+            4 => Ok(GridPrecision::Square),
+            6 => Ok(GridPrecision::Subsquare),
+            8 => Ok(GridPrecision::Extended),
+            10 => Ok(GridPrecision::SuperExtended),
+            12 => Ok(GridPrecision::HyperExtended),
+            p => Err(MHError::InvalidPrecision(p)),
+        }
+    }
+}
+
+// Same as longlat_to_grid(), but with a precision that is always valid
+pub fn longlat_to_grid_typed(
+    long: f64,
+    lat: f64,
+    precision: GridPrecision,
+) -> Result<String, MHError> {
+    longlat_to_grid(long, lat, precision as usize)
+}
+
+// The precision of a grid, e.g. GridPrecision::Subsquare for "FM18lv"
+pub fn grid_level(grid: &str) -> Result<GridPrecision, MHError> {
+    validate_grid(grid)?;
+    GridPrecision::try_from(grid.len())
+}
+
+// How precisely a grid pins down a location, e.g. for warning about coarse
+// grids in a UI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GridConfidence {
+    // Fields and squares, tens of km or more
+    Low,
+    // Subsquares, a few km
+    Medium,
+    // Extended squares and finer, within a few hundred meters
+    High,
+}
+
+pub fn grid_confidence(grid: &str) -> Result<GridConfidence, MHError> {
+    Ok(match grid_level(grid)? {
+        GridPrecision::Field | GridPrecision::Square => GridConfidence::Low,
+        GridPrecision::Subsquare => GridConfidence::Medium,
+        GridPrecision::Extended | GridPrecision::SuperExtended | GridPrecision::HyperExtended => {
+            GridConfidence::High
+        }
+    })
+}
+
+// Distances in km between all pairs of grids, where matrix[i][j] is the
+// distance from grids[i] to grids[j]. Each grid is only converted once, and
+// each pair only calculated once since the matrix is symmetric. The diagonal
+// is always zero.
+pub fn distance_matrix(grids: &[&str]) -> Result<Vec<Vec<f64>>, MHError> {
+    let coords = grids
+        .iter()
+        .map(|g| grid_to_longlat(g))
+        .collect::<Result<Vec<(f64, f64)>, MHError>>()?;
+
+    let mut matrix = vec![vec![0.0; coords.len()]; coords.len()];
+    for i in 0..coords.len() {
+        for j in i + 1..coords.len() {
+            let dist = RADIUS * angular_distance(coords[i], coords[j]);
+            matrix[i][j] = dist;
+            matrix[j][i] = dist;
+        }
+    }
+
+    Ok(matrix)
+}
+
+// Same as distance_matrix(), but fills in the rows in parallel. Every pair is
+// calculated in the same direction as distance_matrix() does, so the results
+// are identical.
+#[cfg(feature = "rayon")]
+pub fn distance_matrix_parallel(grids: &[&str]) -> Result<Vec<Vec<f64>>, MHError> {
+    use rayon::prelude::*;
+
+    let coords = grids
+        .iter()
+        .map(|g| grid_to_longlat(g))
+        .collect::<Result<Vec<(f64, f64)>, MHError>>()?;
+
+    Ok((0..coords.len())
+        .into_par_iter()
+        .map(|i| {
+            (0..coords.len())
+                .map(|j| match i.cmp(&j) {
+                    core::cmp::Ordering::Less => RADIUS * angular_distance(coords[i], coords[j]),
+                    core::cmp::Ordering::Greater => RADIUS * angular_distance(coords[j], coords[i]),
+                    core::cmp::Ordering::Equal => 0.0,
+                })
+                .collect()
+        })
+        .collect())
+}
+
+// The candidate grid whose center is closest to a coordinate, as its index in
+// candidates and the distance to it in km. All candidates are validated
+// before searching.
+pub fn nearest_grid(long: f64, lat: f64, candidates: &[&str]) -> Result<(usize, f64), MHError> {
+    validate_longlat(long, lat)?;
+    let coords = candidates
+        .iter()
+        .map(|g| grid_to_longlat(g))
+        .collect::<Result<Vec<(f64, f64)>, MHError>>()?;
+
+    coords
+        .into_iter()
+        .map(|c| RADIUS * angular_distance((long, lat), c))
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .ok_or(MHError::NoGrids)
+}
+
+// How longlat_to_grid_rounded() assigns a coordinate on the boundary between
+// two cells. The rule is that a point on a boundary belongs to the cell east
+// or north of it, i.e. the cell whose south west corner it is. The exception
+// is 180 longitude and 90 latitude, which belong to the last cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    // Take the floor of the computed offsets, exactly like longlat_to_grid().
+    // Most cell boundaries (such as 5 minute subsquares) can't be represented
+    // exactly as floating point degrees, so a point on the boundary can end up
+    // in the cell to the west or south of it.
+    Floor,
+    // Snap points within 1e-9 degrees (about 0.1 mm) of a boundary onto it,
+    // so that they reliably end up in the cell east or north of it.
+    Nearest,
+}
+
+// Same as longlat_to_grid(), with control over how points on the boundary
+// between cells are assigned, see Rounding.
+pub fn longlat_to_grid_rounded(
+    long: f64,
+    lat: f64,
+    precision: usize,
+    rounding: Rounding,
+) -> Result<String, MHError> {
+    if rounding == Rounding::Floor {
+        return longlat_to_grid(long, lat, precision);
+    }
+
+    validate_precision(precision)?;
+    validate_longlat(long, lat)?;
+
+    // Rather than calculating each position separately, find the cell at the
+    // full precision, so that snapping onto a boundary carries over into all
+    // coarser positions
+    let (width, height) = cell_dims(precision);
+    let cells = cells_per_axis(precision);
+    let index = |x: f64, size: f64| {
+        let v = x / size;
+        let v = if (v.round() - v).abs() * size < 1e-9 {
+            v.round()
+        } else {
+            v.floor()
+        };
+        (v as u64).min(cells - 1)
+    };
+    let col = index(long + LONG_OFFSET, width);
+    let row = index(lat + LAT_OFFSET, height);
+
+    Ok(cell_to_grid(col, row, precision))
+}
+
+// The area of a grid in km², assuming a spherical earth with the mean radius.
+// Grids are bounded by meridians and parallels, so the area is exactly
+// R² ⋅ Δλ ⋅ (sin φ2 − sin φ1)
+// which shrinks toward the poles as the meridians converge.
+pub fn grid_area_km2(grid: &str) -> Result<f64, MHError> {
+    let (min_long, min_lat, max_long, max_lat) = grid_bounds(grid)?;
+    #[allow(non_snake_case)]
+    let Δλ = (max_long - min_long).to_radians();
+    let area = RADIUS.powi(2) * Δλ * (max_lat.to_radians().sin() - min_lat.to_radians().sin());
+    Ok(area)
+}
+
+// Roughly how far in km any point of a grid can be from its center, i.e. half
+// the diagonal of the grid. The width of a grid shrinks with the cosine of
+// the latitude of its center.
+pub fn grid_uncertainty_km(grid: &str) -> Result<f64, MHError> {
+    let (width, height) = cell_dimensions_km(grid)?;
+    Ok((width.powi(2) + height.powi(2)).sqrt() / 2.0)
+}
+
+// The approximate (width, height) in km of a grid, e.g. for scale bars. The
+// width is measured along the latitude of the grid's center, so it shrinks
+// toward the poles, while the height only depends on the precision.
+pub fn cell_dimensions_km(grid: &str) -> Result<(f64, f64), MHError> {
+    let loc = grid_to_longlat_detailed(grid)?;
+    let km_per_deg = RADIUS * core::f64::consts::PI / 180.0;
+    Ok((
+        loc.cell_width_deg * loc.lat.to_radians().cos() * km_per_deg,
+        loc.cell_height_deg * km_per_deg,
+    ))
+}
+
+// UTM (Universal Transverse Mercator) coordinates, as used by GIS and on
+// topographic maps. They're written as zone and latitude band, easting and
+// northing in meters, e.g. "17T 630084 4833439". Converting between grids and
+// UTM goes through long/lat, which grid_to_utm() and utm_to_grid() wrap up:
+//
+//   grid -> grid_to_longlat() -> longlat_to_utm() -> UTM
+//   UTM -> utm_to_longlat() -> longlat_to_grid() -> grid
+//
+// The projection uses the Krüger series on WGS84, see
+// https://en.wikipedia.org/wiki/Transverse_Mercator_projection, which is
+// accurate to well within a millimeter inside a zone. UTM only covers 80°S to
+// 84°N, the polar regions use UPS instead which isn't supported.
+
+static UTM_K0: f64 = 0.9996;
+static UTM_E0: f64 = 500000.0;
+static UTM_N0_SOUTH: f64 = 10000000.0;
+static UTM_BANDS: &str = "CDEFGHJKLMNPQRSTUVWX";
+
+// Series coefficients for the projection, from the third flattening n:
+// (rectifying radius A, forward α, inverse β, latitude δ)
+fn utm_series() -> (f64, [f64; 3], [f64; 3], [f64; 3]) {
+    let n = WGS84_F / (2.0 - WGS84_F);
+    let (n2, n3) = (n * n, n * n * n);
+    let a = WGS84_A * 1000.0 / (1.0 + n) * (1.0 + n2 / 4.0 + n2 * n2 / 64.0);
+    let alpha = [
+        n / 2.0 - 2.0 * n2 / 3.0 + 5.0 * n3 / 16.0,
+        13.0 * n2 / 48.0 - 3.0 * n3 / 5.0,
+        61.0 * n3 / 240.0,
+    ];
+    let beta = [
+        n / 2.0 - 2.0 * n2 / 3.0 + 37.0 * n3 / 96.0,
+        n2 / 48.0 + n3 / 15.0,
+        17.0 * n3 / 480.0,
+    ];
+    let delta = [
+        2.0 * n - 2.0 * n2 / 3.0 - 2.0 * n3,
+        7.0 * n2 / 3.0 - 8.0 * n3 / 5.0,
+        56.0 * n3 / 15.0,
+    ];
+    (a, alpha, beta, delta)
+}
+
+// The UTM zone of a coordinate, including the exceptions for southern Norway
+// and Svalbard
+fn utm_zone(long: f64, lat: f64) -> u32 {
+    if (56.0..64.0).contains(&lat) && (3.0..12.0).contains(&long) {
+        return 32;
+    }
+    if lat >= 72.0 && (0.0..42.0).contains(&long) {
+        return match long {
+            l if l < 9.0 => 31,
+            l if l < 21.0 => 33,
+            l if l < 33.0 => 35,
+            _ => 37,
+        };
+    }
+    (((long + 180.0) / 6.0) as u32 + 1).min(60)
+}
+
+// Convert long/lat to a UTM coordinate string, rounded to the meter
+pub fn longlat_to_utm(long: f64, lat: f64) -> Result<String, MHError> {
+    validate_longlat(long, lat)?;
+    if !(-80.0..=84.0).contains(&lat) {
+        return Err(MHError::InvalidLongLat(long, lat));
+    }
+
+    let zone = utm_zone(long, lat);
+    // Band X is 12 degrees tall, covering up to 84N
+    let band_idx = (((lat + 80.0) / 8.0) as usize).min(UTM_BANDS.len() - 1);
+    let band = UTM_BANDS.as_bytes()[band_idx] as char;
+
+    let (a, alpha, _, _) = utm_series();
+    let e = (WGS84_F * (2.0 - WGS84_F)).sqrt();
+    let λ0 = (zone as f64 * 6.0 - 183.0).to_radians();
+    let φ = lat.to_radians();
+    #[allow(non_snake_case)]
+    let Δλ = long.to_radians() - λ0;
+
+    let t = (φ.sin().atanh() - e * (e * φ.sin()).atanh()).sinh();
+    let xi = (t / Δλ.cos()).atan();
+    let eta = (Δλ.sin() / (1.0 + t * t).sqrt()).atanh();
+
+    let mut easting = eta;
+    let mut northing = xi;
+    for (j, coef) in alpha.iter().enumerate() {
+        let j2 = 2.0 * (j + 1) as f64;
+        easting += coef * (j2 * xi).cos() * (j2 * eta).sinh();
+        northing += coef * (j2 * xi).sin() * (j2 * eta).cosh();
+    }
+    let easting = UTM_E0 + UTM_K0 * a * easting;
+    let mut northing = UTM_K0 * a * northing;
+    if lat < 0.0 {
+        northing += UTM_N0_SOUTH;
+    }
+
+    Ok(format!("{}{} {:.0} {:.0}", zone, band, easting, northing))
+}
+
+// Parse a UTM coordinate string like "17T 630084 4833439" (or "17 T ...") and
+// convert it to long/lat
+pub fn utm_to_longlat(utm: &str) -> Result<(f64, f64), MHError> {
+    let err = || MHError::InvalidUtm(utm.to_string());
+
+    let mut parts = utm.split_whitespace();
+    let mut zone = parts.next().ok_or_else(err)?.to_string();
+    if zone.chars().all(|c| c.is_ascii_digit()) {
+        // Zone and band separated by a space
+        zone.push_str(parts.next().ok_or_else(err)?);
+    }
+    let easting = parts.next().and_then(|e| e.parse::<f64>().ok());
+    let northing = parts.next().and_then(|n| n.parse::<f64>().ok());
+    let (Some(easting), Some(northing), None) = (easting, northing, parts.next()) else {
+        return Err(err());
+    };
+
+    let band = zone.pop().ok_or_else(err)?.to_ascii_uppercase();
+    let zone = zone.parse::<u32>().map_err(|_| err())?;
+    if !(1..=60).contains(&zone) || !UTM_BANDS.contains(band) {
+        return Err(err());
+    }
+    if !(0.0..1000000.0).contains(&easting) || !(0.0..=UTM_N0_SOUTH).contains(&northing) {
+        return Err(err());
+    }
+
+    let northing = if band < 'N' {
+        northing - UTM_N0_SOUTH
+    } else {
+        northing
+    };
+
+    let (a, _, beta, delta) = utm_series();
+    let xi = northing / (UTM_K0 * a);
+    let eta = (easting - UTM_E0) / (UTM_K0 * a);
+
+    let mut xi1 = xi;
+    let mut eta1 = eta;
+    for (j, coef) in beta.iter().enumerate() {
+        let j2 = 2.0 * (j + 1) as f64;
+        xi1 -= coef * (j2 * xi).sin() * (j2 * eta).cosh();
+        eta1 -= coef * (j2 * xi).cos() * (j2 * eta).sinh();
+    }
+    let chi = (xi1.sin() / eta1.cosh()).asin();
+    let mut φ = chi;
+    for (j, coef) in delta.iter().enumerate() {
+        φ += coef * (2.0 * (j + 1) as f64 * chi).sin();
+    }
+    let λ0 = (zone as f64 * 6.0 - 183.0).to_radians();
+    let λ = λ0 + (eta1.sinh() / xi1.cos()).atan();
+
+    Ok((λ.to_degrees(), φ.to_degrees()))
+}
+
+// The UTM coordinate of a grid's center
+pub fn grid_to_utm(grid: &str) -> Result<String, MHError> {
+    let (long, lat) = grid_to_longlat(grid)?;
+    longlat_to_utm(long, lat)
+}
+
+// The grid of the given precision that a UTM coordinate is in
+pub fn utm_to_grid(utm: &str, precision: usize) -> Result<String, MHError> {
+    let (long, lat) = utm_to_longlat(utm)?;
+    longlat_to_grid(long, lat, precision)
+}
+
+static COMPASS_POINTS: [&str; 16] = [
+    "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW",
+    "NNW",
+];
+
+// The 16 point compass direction of a bearing in degrees. Each point covers
+// 22.5 degrees centered on its direction, and a bearing exactly on the edge
+// between two points goes to the clockwise one, so 11.25 is NNE while just
+// below it is N.
+fn compass_point(bearing: f64) -> &'static str {
+    let sector = ((bearing + 11.25) / 22.5) as usize % 16;
+    COMPASS_POINTS[sector]
+}
+
+// The bearing between two grids as a compass point, e.g. "NNE" or "SW"
+pub fn grid_bearing_compass(from: &str, to: &str) -> Result<String, MHError> {
+    let bearing = grid_bearing(from, to)?;
+    Ok(compass_point(bearing).to_string())
+}
+
+// The distance in km from the center of a grid to the great circle through
+// the centers of two other grids:
+// dxt = asin( sin δ13 ⋅ sin(θ13 − θ12) ) ⋅ R
+// where δ13 is the angular distance from the path start to the point, and θ13
+// and θ12 the bearings from the path start to the point and path end. The
+// distance is positive when the point is to the right of the path (looking
+// from path_from to path_to), and negative to the left.
+pub fn cross_track_distance(path_from: &str, path_to: &str, point: &str) -> Result<f64, MHError> {
+    let start = grid_to_longlat(path_from)?;
+    let end = grid_to_longlat(path_to)?;
+    let point = grid_to_longlat(point)?;
+
+    let δ13 = angular_distance(start, point);
+    let θ13 = initial_bearing(start, point).to_radians();
+    let θ12 = initial_bearing(start, end).to_radians();
+
+    Ok((δ13.sin() * (θ13 - θ12).sin()).asin() * RADIUS)
+}
+
+// A random grid of the given precision, with every cell equally likely. This
+// always produces a valid grid, so it's useful for fuzzing.
+#[cfg(feature = "rand")]
+pub fn random_grid(precision: usize) -> Result<String, MHError> {
+    random_grid_with(&mut rand::rng(), precision)
+}
+
+// Same as random_grid(), with a given random number generator, e.g. a seeded
+// one for reproducible tests
+#[cfg(feature = "rand")]
+pub fn random_grid_with<R: rand::Rng + ?Sized>(
+    rng: &mut R,
+    precision: usize,
+) -> Result<String, MHError> {
+    validate_precision(precision)?;
+    let cells = cells_per_axis(precision);
+    let col = rng.random_range(0..cells);
+    let row = rng.random_range(0..cells);
+    Ok(cell_to_grid(col, row, precision))
+}
+
+// The grid on the opposite side of the earth from the center of a grid
+pub fn grid_antipode(grid: &str, precision: usize) -> Result<String, MHError> {
+    let (long, lat) = grid_to_longlat(grid)?;
+    let long = if long > 0.0 {
+        long - 180.0
+    } else {
+        long + 180.0
+    };
+    longlat_to_grid(long, -lat, precision)
+}
+
+// Conversions to and from the geo-types Point and Rect, which the geo crate's
+// algorithms work on. Points are (x, y) = (long, lat).
+#[cfg(feature = "geo")]
+pub fn grid_to_point(grid: &str) -> Result<geo_types::Point<f64>, MHError> {
+    let (long, lat) = grid_to_longlat(grid)?;
+    Ok(geo_types::Point::new(long, lat))
+}
+
+#[cfg(feature = "geo")]
+pub fn point_to_grid(point: geo_types::Point<f64>, precision: usize) -> Result<String, MHError> {
+    longlat_to_grid(point.x(), point.y(), precision)
+}
+
+#[cfg(feature = "geo")]
+pub fn grid_to_rect(grid: &str) -> Result<geo_types::Rect<f64>, MHError> {
+    let (min_long, min_lat, max_long, max_lat) = grid_bounds(grid)?;
+    Ok(geo_types::Rect::new(
+        geo_types::coord! { x: min_long, y: min_lat },
+        geo_types::coord! { x: max_long, y: max_lat },
+    ))
+}
+
+// Every grid of the given precision, in the same row major order as
+// grids_in_box(). The number of grids grows quickly with the precision:
+//
+//   2: 324
+//   4: 32,400
+//   6: 18,662,400
+//   8: 1,866,240,000
+//  10: 1,074,954,240,000
+//  12: 107,495,424,000,000
+pub fn all_grids(precision: usize) -> Result<impl Iterator<Item = String>, MHError> {
+    validate_precision(precision)?;
+    let cells = cells_per_axis(precision);
+    Ok(
+        (0..cells)
+            .flat_map(move |row| (0..cells).map(move |col| cell_to_grid(col, row, precision))),
+    )
+}
+
+// Total distance in km of a route through a sequence of grids, from center to
+// center. An invalid grid is reported along with its index in the route.
+pub fn path_distance(grids: &[&str]) -> Result<f64, MHError> {
+    let coords = grids
+        .iter()
+        .enumerate()
+        .map(|(i, g)| grid_to_longlat(g).map_err(|e| MHError::InvalidPathGrid(i, Box::new(e))))
+        .collect::<Result<Vec<(f64, f64)>, MHError>>()?;
+
+    Ok(coords
+        .windows(2)
+        .map(|pair| RADIUS * angular_distance(pair[0], pair[1]))
+        .sum())
+}
+
+// How far into its grid cell of the given precision a coordinate is, as a
+// fraction (0..1) of the cell width and height from the south west corner.
+// This is measured within the grid that longlat_to_grid() returns, so it
+// agrees with which cell the coordinate is in, even right at an edge where
+// rounding decides. Exactly 180 longitude or 90 latitude belong to the last
+// cell, and are at 1.0.
+pub fn coord_in_cell(long: f64, lat: f64, precision: usize) -> Result<(f64, f64), MHError> {
+    let grid = longlat_to_grid(long, lat, precision)?;
+    let (west, south) = decode_corner(&grid);
+    let (width, height) = cell_dims(precision);
+    let fraction = |value: f64, corner: f64, size: f64, max: f64| {
+        if value >= max {
+            1.0
+        } else {
+            ((value - corner) / size).clamp(0.0, 1.0)
+        }
+    };
+    Ok((
+        fraction(long, west, width, LONG_OFFSET),
+        fraction(lat, south, height, LAT_OFFSET),
+    ))
+}
+
+// Same as longlat_to_grid(), but silently clamps out of range coordinates to
+// the nearest valid one instead of returning an error, e.g. for GPS fixes
+// with noise just past a pole. Only an invalid precision or NaN is an error.
+pub fn longlat_to_grid_clamped(long: f64, lat: f64, precision: usize) -> Result<String, MHError> {
+    longlat_to_grid(
+        long.clamp(-LONG_OFFSET, LONG_OFFSET),
+        lat.clamp(-LAT_OFFSET, LAT_OFFSET),
+        precision,
+    )
+}
+
+// Shorten a grid to a lower precision, e.g. "FM18lv53" to "FM18", which is the
+// grid it's within. A grid can't be made longer, as that would make up
+// precision it doesn't have.
+pub fn grid_truncate(grid: &str, new_precision: usize) -> Result<String, MHError> {
+    let mut grid = normalize_grid(grid)?;
+    match new_precision {
+        2 | 4 | 6 | 8 | 10 | 12 if new_precision <= grid.len() => {}
+        p => return Err(MHError::InvalidPrecision(p)),
+    }
+    grid.truncate(new_precision);
+    Ok(grid)
+}
+
+// Check that converting a grid to long/lat and back gives the same grid, in
+// canonical casing. This holds for every valid grid, so it's mostly useful as
+// an invariant for fuzzing.
+pub fn assert_roundtrip(grid: &str) -> Result<(), MHError> {
+    let grid = normalize_grid(grid)?;
+    let (long, lat) = grid_to_longlat(&grid)?;
+    let back = longlat_to_grid(long, lat, grid.len())?;
+    if back != grid {
+        return Err(MHError::RoundTripMismatch(grid, back));
+    }
+    Ok(())
+}
+
+// Same as longlat_to_grid(), along with how far in km the center of the grid
+// is from the coordinate, i.e. the precision lost to the conversion
+pub fn longlat_to_grid_with_error(
+    long: f64,
+    lat: f64,
+    precision: usize,
+) -> Result<(String, f64), MHError> {
+    let grid = longlat_to_grid(long, lat, precision)?;
+    let center = decode_grid(&grid);
+    let error = RADIUS * angular_distance((long, lat), center);
+    Ok((grid, error))
+}
+
+// The grid in an ADIF (Amateur Data Interchange Format) log field, e.g.
+// "<GRIDSQUARE:6>FM18lv". Fields are <NAME:LENGTH[:TYPE]> followed by LENGTH
+// characters of data, anything after that is ignored. Both the GRIDSQUARE and
+// MY_GRIDSQUARE fields are accepted, and the grid is returned in canonical
+// casing.
+pub fn grid_from_adif_field(field: &str) -> Result<String, MHError> {
+    let err = || MHError::InvalidAdif(field.to_string());
+
+    let (header, data) = field
+        .trim_start()
+        .strip_prefix('<')
+        .and_then(|f| f.split_once('>'))
+        .ok_or_else(err)?;
+    let mut parts = header.split(':');
+    let name = parts.next().ok_or_else(err)?;
+    let len: usize = parts.next().and_then(|l| l.parse().ok()).ok_or_else(err)?;
+    if parts.count() > 1
+        || !(name.eq_ignore_ascii_case("GRIDSQUARE") || name.eq_ignore_ascii_case("MY_GRIDSQUARE"))
+    {
+        return Err(err());
+    }
+
+    let grid = data.get(..len).ok_or_else(err)?;
+    normalize_grid(grid)
+}
+
+// The column and row of the cell at the next finer level that a coordinate is
+// in, within its grid of the parent precision. E.g. the subsquare "lv" of
+// "FM18lv" is at column 11 and row 21 within "FM18". The parent precision can
+// be at most 10, as there's no finer level than 12 characters.
+pub fn subcell_index(long: f64, lat: f64, parent_precision: usize) -> Result<(u32, u32), MHError> {
+    match parent_precision {
+        2 | 4 | 6 | 8 | 10 => {}
+        p => return Err(MHError::InvalidPrecision(p)),
+    }
+    validate_longlat(long, lat)?;
+
+    let canonical = CANONICAL.as_bytes();
+    let mut buf = [0u8; 12];
+    let (col, row) = (parent_precision, parent_precision + 1);
+    encode_grid(long, lat, &mut buf[..row + 1]);
+    Ok((
+        (buf[col] - canonical[col]) as u32,
+        (buf[row] - canonical[row]) as u32,
+    ))
+}
+
+// The smallest grid that contains both grids, i.e. their longest common
+// prefix of whole pairs. Grids in different fields have nothing in common,
+// which is an error.
+pub fn common_grid(a: &str, b: &str) -> Result<String, MHError> {
+    let a = normalize_grid(a)?;
+    let b = normalize_grid(b)?;
+    let pairs = a
+        .as_bytes()
+        .chunks(2)
+        .zip(b.as_bytes().chunks(2))
+        .take_while(|(x, y)| x == y)
+        .count();
+    if pairs == 0 {
+        return Err(MHError::NoCommonGrid(a, b));
+    }
+    Ok(a[..pairs * 2].to_string())
+}
+
+// A GeoJSON Feature with the grid as a Polygon, and the grid itself as the
+// "grid" property, e.g. for drawing it with Leaflet. The ring starts and ends
+// at the south west corner, and is counterclockwise as RFC 7946 wants.
+pub fn grid_to_geojson(grid: &str) -> Result<String, MHError> {
+    let corners = grid_corners(grid)?;
+    let ring = corners
+        .iter()
+        .chain(corners.first())
+        .map(|(long, lat)| format!("[{},{}]", long, lat))
+        .collect::<Vec<String>>()
+        .join(",");
+    Ok(format!(
+        r#"{{"type":"Feature","geometry":{{"type":"Polygon","coordinates":[[{}]]}},"properties":{{"grid":"{}"}}}}"#,
+        ring,
+        normalize_grid(grid)?
+    ))
+}
+
+// The (long, lat) size in degrees of a grid of the given precision, e.g.
+// (2.0, 1.0) for a 4 character square
+pub fn cell_size(precision: usize) -> Result<(f64, f64), MHError> {
+    validate_precision(precision)?;
+    Ok(cell_dims(precision))
+}
+
+// Whether a grid is at the ±180 longitude edge, i.e. in the first or last
+// column of grids of its precision. Grids never actually span the
+// antimeridian, but their east or west neighbor is on the other side of it.
+pub fn grid_crosses_antimeridian(grid: &str) -> Result<bool, MHError> {
+    validate_grid(grid)?;
+    let (col, _) = grid_to_cell(grid);
+    Ok(col == 0 || col == cells_per_axis(grid.len()) - 1)
+}
+
+// Whether a grid touches the north or south pole, i.e. is in the first or
+// last row of grids of its precision
+pub fn grid_touches_pole(grid: &str) -> Result<bool, MHError> {
+    validate_grid(grid)?;
+    let (_, row) = grid_to_cell(grid);
+    Ok(row == 0 || row == cells_per_axis(grid.len()) - 1)
+}
+
+// Lazily convert a whitespace separated list of grids, e.g. a line of a data
+// file, to the long/lat of each. Each grid is converted on its own, so an
+// invalid grid doesn't stop the rest from being parsed.
+pub fn parse_grids<'a>(input: &'a str) -> impl Iterator<Item = Result<(f64, f64), MHError>> + 'a {
+    input.split_whitespace().map(grid_to_longlat)
+}
+
+// The pairs of a grid, in canonical casing, e.g. field "FM", square "18" and
+// subsquare "lv" of "FM18lv". The pairs past the length of the grid are None.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GridComponents {
+    pub field: String,
+    pub square: Option<String>,
+    pub subsquare: Option<String>,
+    pub extended: Option<String>,
+    pub superextended: Option<String>,
+    pub hyperextended: Option<String>,
+}
+
+pub fn grid_components(grid: &str) -> Result<GridComponents, MHError> {
+    let grid = normalize_grid(grid)?;
+    let pair = |i: usize| grid.get(i * 2..i * 2 + 2).map(|p| p.to_string());
+    Ok(GridComponents {
+        field: grid[0..2].to_string(),
+        square: pair(1),
+        subsquare: pair(2),
+        extended: pair(3),
+        superextended: pair(4),
+        hyperextended: pair(5),
+    })
+}
+
+// Distances and bearings from a fixed home grid, which is only converted once
+// rather than on every call to grid_distance() and grid_bearing()
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridDistanceCalculator {
+    home: (f64, f64),
+}
+
+impl GridDistanceCalculator {
+    pub fn new(home: &str) -> Result<Self, MHError> {
+        Ok(GridDistanceCalculator {
+            home: grid_to_longlat(home)?,
+        })
+    }
+
+    pub fn distance_to(&self, grid: &str) -> Result<f64, MHError> {
+        Ok(RADIUS * angular_distance(self.home, grid_to_longlat(grid)?))
+    }
+
+    pub fn bearing_to(&self, grid: &str) -> Result<f64, MHError> {
+        Ok(initial_bearing(self.home, grid_to_longlat(grid)?))
+    }
+}
+
+// Convert (long, lat) pairs to grids in method call style, e.g.
+// `(-77.035278, 38.889484).to_grid(6)?`
+pub trait ToGrid {
+    fn to_grid(&self, precision: usize) -> Result<String, MHError>;
+}
+
+impl ToGrid for (f64, f64) {
+    fn to_grid(&self, precision: usize) -> Result<String, MHError> {
+        longlat_to_grid(self.0, self.1, precision)
+    }
+}
+
+// How many grids east and north one grid is from another of the same
+// precision, as (columns, rows). Negative is west or south. Columns wrap
+// around the antimeridian whichever way is shorter, so the column offset is
+// at most half way around the world.
+pub fn grid_offset(from: &str, to: &str) -> Result<(i64, i64), MHError> {
+    validate_grid(from)?;
+    validate_grid(to)?;
+    if from.len() != to.len() {
+        return Err(MHError::PrecisionMismatch(from.len(), to.len()));
+    }
+
+    let cells = cells_per_axis(from.len()) as i64;
+    let (from_col, from_row) = grid_to_cell(from);
+    let (to_col, to_row) = grid_to_cell(to);
+
+    let mut cols = (to_col as i64 - from_col as i64).rem_euclid(cells);
+    if cols > cells / 2 {
+        cols -= cells;
+    }
+    Ok((cols, to_row as i64 - from_row as i64))
+}
+
+// Every grid one level finer within a grid, in the same row major order as
+// grids_in_box(). The number of children alternates between the levels:
+// 100 (10×10) for fields, subsquares and superextended squares, and 576
+// (24×24) for squares and extended squares. There is nothing finer than a 12
+// character grid, so that returns an InvalidPrecision error.
+pub fn subgrids(grid: &str) -> Result<Vec<String>, MHError> {
+    let grid = normalize_grid(grid)?;
+    let precision = grid.len() + 2;
+    validate_precision(precision)?;
+
+    let canonical = CANONICAL.as_bytes();
+    let cells = LEVEL_CELLS[grid.len() / 2] as u8;
+    let mut children = Vec::with_capacity(cells as usize * cells as usize);
+    for row in 0..cells {
+        for col in 0..cells {
+            let mut child = grid.clone();
+            child.push((canonical[grid.len()] + col) as char);
+            child.push((canonical[grid.len() + 1] + row) as char);
+            children.push(child);
+        }
+    }
+    Ok(children)
+}
+
+// A character of a grid that isn't within the range of its position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BadPosition {
+    pub position: usize,
+    pub found: char,
+    pub expected: CharClass,
+}
+
+// Everything that is wrong with a grid, rather than just the first problem
+// like validating it does. Useful for figuring out how a dataset got mangled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GridDiagnosis {
+    // Whether the grid is 2/4/6/8/10/12 characters long
+    pub length_valid: bool,
+    // Only the first 12 characters are checked
+    pub bad_positions: Vec<BadPosition>,
+}
+
+impl GridDiagnosis {
+    pub fn is_valid(&self) -> bool {
+        self.length_valid && self.bad_positions.is_empty()
+    }
+}
+
+pub fn diagnose_grid(grid: &str) -> GridDiagnosis {
+    GridDiagnosis {
+        length_valid: matches!(grid.chars().count(), 2 | 4 | 6 | 8 | 10 | 12),
+        bad_positions: grid
+            .chars()
+            .take(12)
+            .enumerate()
+            .filter(|&(i, c)| !CharClass::at(i).matches(c))
+            .map(|(position, found)| BadPosition {
+                position,
+                found,
+                expected: CharClass::at(position),
+            })
+            .collect(),
+    }
+}
+
+// The grid at the geographic centroid of a number of grid centers. The
+// centers are averaged as 3D vectors rather than by long/lat, which would
+// break across the antimeridian. Grids spread evenly around the earth have no
+// meaningful centroid, and give an arbitrary grid.
+pub fn grids_centroid(grids: &[&str], precision: usize) -> Result<String, MHError> {
+    if grids.is_empty() {
+        return Err(MHError::NoGrids);
+    }
+
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    for grid in grids {
+        let (long, lat) = grid_to_longlat(grid)?;
+        let (λ, φ) = (long.to_radians(), lat.to_radians());
+        x += φ.cos() * λ.cos();
+        y += φ.cos() * λ.sin();
+        z += φ.sin();
+    }
+
+    let φ = z.atan2((x * x + y * y).sqrt());
+    let λ = y.atan2(x);
+    longlat_to_grid(λ.to_degrees(), φ.to_degrees(), precision)
+}
+
+// The center of the grid of the given precision that a coordinate is in, i.e.
+// the coordinate quantized to the grid. Same as longlat_to_grid() followed by
+// grid_to_longlat(), without validating the grid in between.
+pub fn snap_to_grid_center(long: f64, lat: f64, precision: usize) -> Result<(f64, f64), MHError> {
+    let grid = longlat_to_grid(long, lat, precision)?;
+    Ok(decode_grid(&grid))
+}
+
+// The sun's (azimuth, elevation) in degrees as seen from the center of a grid
+// at the given unix time, for working out the gray line. This is the NOAA
+// solar calculator's algorithm, which is good to well within a degree for
+// several centuries around the present. The elevation is geometric, without
+// correcting for atmospheric refraction, and the azimuth is clockwise from
+// north.
+#[cfg(feature = "solar")]
+pub fn solar_position(grid: &str, unixtime: i64) -> Result<(f64, f64), MHError> {
+    let (long, lat) = grid_to_longlat(grid)?;
+
+    // Julian centuries since J2000.0
+    let jd = unixtime as f64 / 86400.0 + 2440587.5;
+    let t = (jd - 2451545.0) / 36525.0;
+
+    let mean_long = (280.46646 + t * (36000.76983 + t * 0.0003032)) % 360.0;
+    let mean_anom = (357.52911 + t * (35999.05029 - 0.0001537 * t)).to_radians();
+    let ecc = 0.016708634 - t * (0.000042037 + 0.0000001267 * t);
+    let center = mean_anom.sin() * (1.914602 - t * (0.004817 + 0.000014 * t))
+        + (2.0 * mean_anom).sin() * (0.019993 - 0.000101 * t)
+        + (3.0 * mean_anom).sin() * 0.000289;
+    let omega = (125.04 - 1934.136 * t).to_radians();
+    let app_long = (mean_long + center - 0.00569 - 0.00478 * omega.sin()).to_radians();
+
+    let mean_obliq =
+        23.0 + (26.0 + (21.448 - t * (46.815 + t * (0.00059 - t * 0.001813))) / 60.0) / 60.0;
+    let obliq = (mean_obliq + 0.00256 * omega.cos()).to_radians();
+    let decl = (obliq.sin() * app_long.sin()).asin();
+
+    // Equation of time, in minutes
+    let l0 = mean_long.to_radians();
+    let y = (obliq / 2.0).tan().powi(2);
+    let eq_time = 4.0
+        * (y * (2.0 * l0).sin() - 2.0 * ecc * mean_anom.sin()
+            + 4.0 * ecc * y * mean_anom.sin() * (2.0 * l0).cos()
+            - 0.5 * y * y * (4.0 * l0).sin()
+            - 1.25 * ecc * ecc * (2.0 * mean_anom).sin())
+        .to_degrees();
+
+    let minutes = unixtime.rem_euclid(86400) as f64 / 60.0;
+    let solar_time = minutes + eq_time + 4.0 * long;
+    let hour_angle = (solar_time / 4.0 - 180.0).to_radians();
+
+    let φ = lat.to_radians();
+    let elevation = (φ.sin() * decl.sin() + φ.cos() * decl.cos() * hour_angle.cos())
+        .clamp(-1.0, 1.0)
+        .asin();
+    let azimuth = hour_angle
+        .sin()
+        .atan2(hour_angle.cos() * φ.sin() - decl.tan() * φ.cos());
+
+    Ok((
+        (azimuth.to_degrees() + 540.0) % 360.0,
+        elevation.to_degrees(),
+    ))
+}
+
+// Like grid_to_longlat(), but for locators longer than any supported
+// precision, e.g. from a future extension beyond 12 characters, or cut off in
+// the middle of a pair. The longest supported prefix is decoded and anything
+// after it is ignored without being validated.
+pub fn grid_to_longlat_prefix(grid: &str) -> Result<(f64, f64), MHError> {
+    let chars = grid.chars().count().min(12);
+    let end = grid
+        .char_indices()
+        .nth(chars - chars % 2)
+        .map_or(grid.len(), |(i, _)| i);
+    grid_to_longlat(&grid[..end])
+}
+
+// The area of a grid in km² on the WGS84 ellipsoid. The ellipsoid is
+// flattened at the poles, which curves it more tightly north-south near the
+// equator and less near the poles. Compared to the spherical grid_area_km2()
+// grids near the equator come out slightly smaller and polar grids slightly
+// larger, by up to almost one percent.
+pub fn grid_area_wgs84_km2(grid: &str) -> Result<f64, MHError> {
+    let (min_long, min_lat, max_long, max_lat) = grid_bounds(grid)?;
+    let e2 = WGS84_F * (2.0 - WGS84_F);
+    let e = e2.sqrt();
+
+    // Area from the equator to a latitude, per radian of longitude, divided by
+    // the square of the semi-minor axis
+    let q = |lat: f64| {
+        let sin = lat.to_radians().sin();
+        sin / (2.0 * (1.0 - e2 * sin * sin)) + (e * sin).atanh() / (2.0 * e)
+    };
+
+    #[allow(non_snake_case)]
+    let Δλ = (max_long - min_long).to_radians();
+    Ok(WGS84_A.powi(2) * (1.0 - e2) * Δλ * (q(max_lat) - q(min_lat)))
+}
+
+// Convert a batch of long/lat coordinates to grids of the given precision.
+// Each coordinate is converted on its own, so one out of range coordinate
+// doesn't stop the rest from being converted.
+pub fn longlat_batch_to_grids(
+    coords: &[(f64, f64)],
+    precision: usize,
+) -> Vec<Result<String, MHError>> {
+    coords
+        .iter()
+        .map(|&(long, lat)| longlat_to_grid(long, lat, precision))
+        .collect()
+}
+
+// Like longlat_to_grid(), but for any float type, e.g. f32 on targets where
+// coordinates are kept in single precision. Note that an f32 only has about
+// seven significant digits, so near ±180 longitude it can only resolve about
+// 1/20 of the width of a 10 character grid, and about half of a 12 character
+// grid. Coordinates close to a grid edge may end up in the neighboring grid.
+#[cfg(feature = "num-traits")]
+pub fn longlat_to_grid_generic<F: num_traits::Float>(
+    long: F,
+    lat: F,
+    precision: usize,
+) -> Result<String, MHError> {
+    let long = long.to_f64().unwrap_or(f64::NAN);
+    let lat = lat.to_f64().unwrap_or(f64::NAN);
+    longlat_to_grid(long, lat, precision)
+}
+
+// The center of a grid as (long, lat) in integer microdegrees, e.g. for a
+// binary protocol where floats might not survive the trip unchanged. Even
+// the smallest grids are more than 15 microdegrees across, so converting back
+// with longlat_to_grid() always gives the same grid.
+pub fn grid_to_microdeg(grid: &str) -> Result<(i32, i32), MHError> {
+    let (long, lat) = grid_to_longlat(grid)?;
+    Ok(((long * 1e6).round() as i32, (lat * 1e6).round() as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The test harness always has std, even when the crate itself doesn't
+    #[cfg(not(feature = "std"))]
+    extern crate std;
+    #[cfg(not(feature = "std"))]
+    use std::println;
+
+    // From https://stackoverflow.com/questions/30856285/assert-eq-with-floating-point-numbers-and-delta
+    macro_rules! assert_delta {
+        ($x:expr, $y:expr, $d:expr) => {
+            let x = $x as f64;
+            let y = $y as f64;
+            if !((x - y).abs() < $d || (y - x).abs() < $d) {
+                panic!();
+            }
+        };
+    }
+
+    // These values come out of the PDF referenced at the top of this file,
+    // except for the last pair of the grid which the PDF doesn't cover
+    static TEST_GRID: &str = "FM18lv53SL34";
+    static TEST_LONG: f64 = -77.035278;
+    static TEST_LAT: f64 = 38.889484;
+
+    fn precision_n(n: usize) {
+        let grid = longlat_to_grid(TEST_LONG, TEST_LAT, n).unwrap();
+        let mut check = String::from(TEST_GRID);
+        check.truncate(n);
+        println!("Grid ({}): {}", n, check);
+        assert_eq!(grid, check);
+    }
+
+    #[test]
+    fn precision_12() {
+        precision_n(12);
+    }
+
+    #[test]
+    fn precision_10() {
+        precision_n(10);
+    }
+
+    #[test]
+    fn precision_8() {
+        precision_n(8);
+    }
+
+    #[test]
+    fn precision_6() {
+        precision_n(6);
+    }
+
+    #[test]
+    fn precision_4() {
+        precision_n(4);
+    }
+
+    #[test]
+    fn precision_2() {
+        precision_n(2);
+    }
+
+    #[test]
+    fn precision_inval() {
+        let grid = longlat_to_grid(TEST_LONG, TEST_LAT, 5);
+        assert!(matches!(grid, Err(MHError::InvalidPrecision(5))));
+    }
+
+    #[test]
+    fn precision_inval_lat() {
+        let grid = longlat_to_grid(TEST_LONG, 921.0, 10);
+        assert!(grid.is_err());
+    }
+
+    #[test]
+    fn precision_inval_long() {
+        let grid = longlat_to_grid(-201.0, TEST_LAT, 10);
+        assert!(grid.is_err());
+    }
+
+    fn longlat_n(n: usize) {
+        let mut grid_in = String::from(TEST_GRID);
+        grid_in.truncate(n);
+
+        let ll = grid_to_longlat(grid_in.as_str());
+        assert!(ll.is_ok());
+
+        // Make sure it's within the margin of error of the smallest field
+        let (long, lat) = ll.unwrap();
+        assert_delta!(long, TEST_LONG, LONG_MULT[n / 2 - 1]);
+        assert_delta!(lat, TEST_LAT, LAT_MULT[n / 2 - 1]);
+
+        // Let's convert it back to grid and compare
+        let grid = longlat_to_grid(long, lat, n).unwrap();
+        assert_eq!(grid_in, grid);
+    }
+
+    #[test]
+    fn longlat12() {
+        longlat_n(12);
+    }
+
+    #[test]
+    fn longlat10() {
+        longlat_n(10);
+    }
+
+    #[test]
+    fn longlat8() {
+        longlat_n(8);
+    }
+
+    #[test]
+    fn longlat6() {
+        longlat_n(6);
+    }
+
+    #[test]
+    fn longlat4() {
+        longlat_n(4);
+    }
+
+    #[test]
+    fn longlat2() {
+        longlat_n(2);
+    }
+
+    #[test]
+    fn longlat_invalid() {
+        let ret = grid_to_longlat("AI021");
+        assert!(ret.is_err());
+        let ret = grid_to_longlat("AIA2");
+        assert!(ret.is_err());
+        let ret = grid_to_longlat("🤷I00");
+        assert!(ret.is_err());
+        let ret = grid_to_longlat("AA00AA00AA00AA");
+        assert!(ret.is_err());
+        let ret = grid_to_longlat("AA00AA00AA0A");
+        assert!(ret.is_err());
+        let ret = grid_to_longlat("AA00AA00AA00");
+        assert!(ret.is_ok());
+        let ret = grid_to_longlat("AA00AA00AA");
+        assert!(ret.is_ok());
+    }
+
+    #[test]
+    fn longlat_invalid_range() {
+        // Just past the end of each position's range
+        for grid in [
+            "SA00",
+            "AS00",
+            "sa00",
+            "AA00ya00",
+            "AA00ay00",
+            "AA00aa00YA",
+            "AA00aa00AY",
+        ] {
+            let ret = grid_to_longlat(grid);
+            assert!(
+                matches!(ret, Err(MHError::InvalidGridChar { .. })),
+                "{}",
+                grid
+            );
+        }
+        let ret = grid_to_longlat("ZZ99");
+        assert!(ret.is_err());
+
+        // And the last valid value of each
+        let ret = grid_to_longlat("RR99xx99XX");
+        assert!(ret.is_ok());
+        let ret = grid_to_longlat("rr99XX99xx");
+        assert!(ret.is_ok());
+    }
+
+    #[test]
+    fn grid_parse() {
+        let grid: Grid = "fm18LV".parse().unwrap();
+        assert_eq!(grid.as_str(), "FM18lv");
+        assert_eq!(grid.precision(), 6);
+        assert_eq!(grid.to_longlat(), grid_to_longlat("FM18lv").unwrap());
+
+        let grid: Grid = "fm18lv53sl".parse().unwrap();
+        assert_eq!(grid.as_str(), "FM18lv53SL");
+        assert_eq!(grid.precision(), 10);
+
+        assert!("FM18l".parse::<Grid>().is_err());
+        assert!("SM18".parse::<Grid>().is_err());
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_grid("Fm18Lv").unwrap(), "FM18lv");
+        assert_eq!(normalize_grid("FM18LV").unwrap(), "FM18lv");
+        assert_eq!(normalize_grid("fm18lv53sl34").unwrap(), TEST_GRID);
+        assert_eq!(normalize_grid(TEST_GRID).unwrap(), TEST_GRID);
+        assert!(normalize_grid("FM18LY").is_err());
+
+        let grid: Grid = "fm18LV".parse().unwrap();
+        assert_eq!(grid.to_string(), "FM18lv");
+    }
+
+    #[test]
+    fn test_distance_null() {
+        let dist = grid_distance(TEST_GRID, TEST_GRID).unwrap();
+        assert_eq!(dist, 0.0);
+    }
+
+    #[test]
+    fn test_distance_home() {
+        let dist = grid_distance("CM87um", "KP04ow").unwrap();
+        let bear = grid_bearing("CM87um", "KP04ow").unwrap();
+        println!("Distance: {} Bearing: {}", dist, bear);
+        println!(
+            "from: {:?} To: {:?}",
+            grid_to_longlat("CM87um"),
+            grid_to_longlat("KP04ow")
+        );
+        assert_delta!(dist, 8189.0, 1.0);
+        assert_delta!(bear, 15.224, 0.001);
+    }
+
+    #[test]
+    fn test_distance_units() {
+        let km = grid_distance("CM87um", "KP04ow").unwrap();
+        let km_in = grid_distance_in("CM87um", "KP04ow", DistanceUnit::Kilometers).unwrap();
+        let mi = grid_distance_in("CM87um", "KP04ow", DistanceUnit::Miles).unwrap();
+        let nm = grid_distance_in("CM87um", "KP04ow", DistanceUnit::NauticalMiles).unwrap();
+        assert_eq!(km, km_in);
+        assert_delta!(mi, 5088.5, 1.0);
+        assert_delta!(nm, 4421.8, 1.0);
+        assert!(grid_distance_in("CM87um", "KP04o", DistanceUnit::Miles).is_err());
+    }
+
+    #[test]
+    fn bounds() {
+        let (min_long, min_lat, max_long, max_lat) = grid_bounds("FM18").unwrap();
+        assert_delta!(min_long, -78.0, 1e-9);
+        assert_delta!(min_lat, 38.0, 1e-9);
+        assert_delta!(max_long - min_long, 2.0, 1e-9);
+        assert_delta!(max_lat - min_lat, 1.0, 1e-9);
+
+        let (min_long, min_lat, max_long, max_lat) = grid_bounds("FM18lv").unwrap();
+        assert_delta!(max_long - min_long, 5.0 / 60.0, 1e-9);
+        assert_delta!(max_lat - min_lat, 2.5 / 60.0, 1e-9);
+
+        // The center is in the middle of the bounds
+        let (long, lat) = grid_to_longlat("FM18lv").unwrap();
+        assert_delta!(long, (min_long + max_long) / 2.0, 1e-9);
+        assert_delta!(lat, (min_lat + max_lat) / 2.0, 1e-9);
+
+        assert!(grid_bounds("FM18l").is_err());
+    }
+
+    #[test]
+    fn corners() {
+        let corners = grid_corners("FM18lv").unwrap();
+        let expected = [
+            (-77.0 - 5.0 / 60.0, 38.875),
+            (-77.0, 38.875),
+            (-77.0, 38.875 + 2.5 / 60.0),
+            (-77.0 - 5.0 / 60.0, 38.875 + 2.5 / 60.0),
+        ];
+        for ((long, lat), (exp_long, exp_lat)) in corners.into_iter().zip(expected) {
+            assert_delta!(long, exp_long, 1e-9);
+            assert_delta!(lat, exp_lat, 1e-9);
+        }
+
+        assert!(grid_corners("FM18l").is_err());
+    }
+
+    #[test]
+    fn center_and_corner() {
+        let center = grid_center("FM18").unwrap();
+        assert_eq!(center, grid_to_longlat("FM18").unwrap());
+        assert_delta!(center.0, -77.0, 1e-9);
+        assert_delta!(center.1, 38.5, 1e-9);
+
+        let (long, lat) = grid_southwest_corner("FM18").unwrap();
+        assert_delta!(long, -78.0, 1e-9);
+        assert_delta!(lat, 38.0, 1e-9);
+        assert_eq!(longlat_to_grid(long, lat, 4).unwrap(), "FM18");
+
+        assert!(grid_center("FM1").is_err());
+        assert!(grid_southwest_corner("FM1").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        let grid: Grid = "fm18LV".parse().unwrap();
+        let json = serde_json::to_string(&grid).unwrap();
+        assert_eq!(json, "\"FM18lv\"");
+        let back: Grid = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.as_str(), grid.as_str());
+
+        let ret: Result<Grid, _> = serde_json::from_str("\"FM18ly\"");
+        assert!(ret.is_err());
+
+        let err = MHError::InvalidGridLength(5);
+        let json = serde_json::to_string(&err).unwrap();
+        let back: MHError = serde_json::from_str(&json).unwrap();
+        assert!(matches!(back, MHError::InvalidGridLength(5)));
+    }
+
+    #[test]
+    fn cells() {
+        for grid in ["AA00", "FM18lv", TEST_GRID, "RR99xx99XX99"] {
+            let (col, row) = grid_to_cell(grid);
+            assert_eq!(cell_to_grid(col, row, grid.len()), grid);
+        }
+        assert_eq!(grid_to_cell("AA00"), (0, 0));
+        assert_eq!(grid_to_cell("RR99"), (179, 179));
+        assert_eq!(grid_to_cell("BA10"), (11, 0));
+    }
+
+    #[test]
+    fn in_box() {
+        let grids: Vec<String> = grids_in_box(-78.5, 38.5, -75.5, 39.5, 4).unwrap().collect();
+        assert_eq!(grids, ["FM08", "FM18", "FM28", "FM09", "FM19", "FM29"]);
+
+        // A box within a single grid
+        let grids: Vec<String> = grids_in_box(-77.5, 38.2, -76.5, 38.7, 4).unwrap().collect();
+        assert_eq!(grids, ["FM18"]);
+
+        let grids: Vec<String> = grids_in_box(-77.45, 38.21, -77.35, 38.29, 6)
+            .unwrap()
+            .collect();
+        assert_eq!(grids, ["FM18gf", "FM18hf", "FM18gg", "FM18hg"]);
+
+        // Exactly the bounds of a grid, which only touches its neighbors
+        let (min_long, min_lat, max_long, max_lat) = grid_bounds("FM18").unwrap();
+        let grids: Vec<String> = grids_in_box(min_long, min_lat, max_long, max_lat, 4)
+            .unwrap()
+            .collect();
+        assert_eq!(grids, ["FM18"]);
+        let (min_long, min_lat, max_long, max_lat) = grid_bounds("FM18lv").unwrap();
+        let grids: Vec<String> = grids_in_box(min_long, min_lat, max_long, max_lat, 6)
+            .unwrap()
+            .collect();
+        assert_eq!(grids, ["FM18lv"]);
+        let grids: Vec<String> = grids_in_box(-78.0, 38.0, -74.0, 40.0, 4).unwrap().collect();
+        assert_eq!(grids, ["FM18", "FM28", "FM19", "FM29"]);
+
+        // Except for the edges of the world
+        assert_eq!(
+            grids_in_box(178.0, 89.0, 180.0, 90.0, 4)
+                .unwrap()
+                .collect::<Vec<_>>(),
+            ["RR99"]
+        );
+
+        // Inverted box
+        assert_eq!(
+            grids_in_box(-75.5, 38.5, -78.5, 39.5, 4).unwrap().count(),
+            0
+        );
+
+        assert!(grids_in_box(-78.5, 38.5, -75.5, 39.5, 5).is_err());
+        assert!(grids_in_box(-78.5, 38.5, -275.5, 39.5, 4).is_err());
+    }
+
+    #[test]
+    fn neighbors() {
+        let neighbors = grid_neighbors("FM18").unwrap();
+        assert_eq!(
+            neighbors,
+            ["FM19", "FM29", "FM28", "FM27", "FM17", "FM07", "FM08", "FM09"]
+        );
+
+        let neighbors = grid_neighbors("fm18lv").unwrap();
+        assert_eq!(
+            neighbors,
+            ["FM18lw", "FM18mw", "FM18mv", "FM18mu", "FM18lu", "FM18ku", "FM18kv", "FM18kw"]
+        );
+
+        // Wrap around the antimeridian
+        let neighbors = grid_neighbors("RL99").unwrap();
+        assert_eq!(
+            neighbors,
+            ["RM90", "AM00", "AL09", "AL08", "RL98", "RL88", "RL89", "RM80"]
+        );
+        let neighbors = grid_neighbors("AA00ax").unwrap();
+        assert_eq!(neighbors[6], "RA90xx");
+
+        // There's nothing north of the north pole
+        assert!(matches!(
+            grid_neighbors("JR09"),
+            Err(MHError::InvalidLongLat(_, _))
+        ));
+        assert!(grid_neighbors("JA00aa").is_err());
+        assert!(grid_neighbors("JA0").is_err());
+    }
+
+    #[test]
+    fn contains() {
+        assert!(grid_contains("FM18", "FM18lv").unwrap());
+        assert!(grid_contains("fm18", "FM18LV53").unwrap());
+        assert!(grid_contains("FM18lv", "FM18lv").unwrap());
+        assert!(!grid_contains("FM18lv", "FM18").unwrap());
+        assert!(!grid_contains("FM18", "FM19lv").unwrap());
+        assert!(!grid_contains("FM18", "EM18lv").unwrap());
+
+        assert!(grid_contains("FM1", "FM18lv").is_err());
+        assert!(grid_contains("FM18", "FM18ly").is_err());
+    }
+
+    #[test]
+    fn path() {
+        let path = great_circle_path("CM87um", "KP04ow", 2).unwrap();
+        assert_eq!(path.len(), 3);
+        assert_eq!(path[0], grid_to_longlat("CM87um").unwrap());
+        assert_eq!(path[2], grid_to_longlat("KP04ow").unwrap());
+
+        // The midpoint is halfway along the great circle
+        let total = angular_distance(path[0], path[2]);
+        let first = angular_distance(path[0], path[1]);
+        let second = angular_distance(path[1], path[2]);
+        assert_delta!(first, total / 2.0, 1e-9);
+        assert_delta!(second, total / 2.0, 1e-9);
+
+        let path = great_circle_path("CM87um", "KP04ow", 10).unwrap();
+        assert_eq!(path.len(), 11);
+        let steps: f64 = path.windows(2).map(|w| angular_distance(w[0], w[1])).sum();
+        assert_delta!(steps, total, 1e-9);
+
+        let path = great_circle_path("CM87um", "CM87um", 4).unwrap();
+        assert_eq!(path.len(), 5);
+
+        let path = great_circle_path("CM87um", "KP04ow", 0).unwrap();
+        assert_eq!(path.len(), 1);
+
+        assert!(matches!(
+            great_circle_path("JJ00", "AI09", 4),
+            Err(MHError::AntipodalGrids(_, _))
+        ));
+        assert!(great_circle_path("CM87u", "KP04ow", 4).is_err());
+    }
+
+    #[test]
+    fn midpoint() {
+        assert_eq!(grid_midpoint("FM18", "FM38", 4).unwrap(), "FM28");
+        assert_eq!(grid_midpoint("FM18lv", "FM18lv", 6).unwrap(), "FM18lv");
+
+        // Between the two grids, not halfway around the world
+        assert_eq!(grid_midpoint("RL89", "AL09", 4).unwrap(), "RL99");
+        assert_eq!(grid_midpoint("AL09", "RL89", 4).unwrap(), "RL99");
+
+        let mid = grid_midpoint("CM87um", "KP04ow", 6).unwrap();
+        let (dist, _) = grid_dist_bearing("CM87um", "KP04ow").unwrap();
+        let (first, _) = grid_dist_bearing("CM87um", &mid).unwrap();
+        let (second, _) = grid_dist_bearing(&mid, "KP04ow").unwrap();
+        assert_delta!(first, dist / 2.0, 10.0);
+        assert_delta!(second, dist / 2.0, 10.0);
+
+        assert!(matches!(
+            grid_midpoint("JJ00", "AI09", 4),
+            Err(MHError::AntipodalGrids(_, _))
+        ));
+        assert!(grid_midpoint("FM18", "FM38", 5).is_err());
+        assert!(grid_midpoint("FM1", "FM38", 4).is_err());
+    }
+
+    #[test]
+    fn test_reverse_bearing() {
+        let (dist, bear, rev) = grid_dist_bearing_full("CM87um", "KP04ow").unwrap();
+        assert_delta!(dist, 8189.0, 1.0);
+        assert_delta!(bear, 15.224, 0.001);
+        assert_eq!(rev, grid_reverse_bearing("CM87um", "KP04ow").unwrap());
+        assert_eq!(rev, grid_bearing("KP04ow", "CM87um").unwrap());
+
+        // Nowhere near the naive forward + 180 on a path this long
+        assert!((rev - (bear + 180.0)).abs() > 10.0);
+        assert_delta!(rev, 330.549, 0.001);
+    }
+
+    #[test]
+    fn test_distance_vincenty() {
+        // Flinders Peak to Buninyong, from Vincenty's original paper
+        let flinders = (
+            144.0 + 25.0 / 60.0 + 29.52440 / 3600.0,
+            -(37.0 + 57.0 / 60.0 + 3.72030 / 3600.0),
+        );
+        let buninyong = (
+            143.0 + 55.0 / 60.0 + 35.38390 / 3600.0,
+            -(37.0 + 39.0 / 60.0 + 10.15610 / 3600.0),
+        );
+        let (dist, bearing) = vincenty_inverse(flinders, buninyong).unwrap();
+        assert_delta!(dist, 54.972271, 1e-6);
+        // 306°52'05.37"
+        assert_delta!(bearing, 306.0 + 52.0 / 60.0 + 5.37 / 3600.0, 1e-6);
+
+        let vincenty = grid_distance_vincenty("CM87um", "KP04ow").unwrap();
+        let haversine = grid_distance("CM87um", "KP04ow").unwrap();
+        assert!(vincenty != haversine);
+        assert!((vincenty - haversine).abs() / haversine < 0.006);
+
+        // Across the antimeridian
+        let vincenty = grid_distance_vincenty("RL89", "AL09").unwrap();
+        let haversine = grid_distance("RL89", "AL09").unwrap();
+        assert!(vincenty != haversine);
+        assert!((vincenty - haversine).abs() / haversine < 0.006);
+
+        assert_eq!(grid_distance_vincenty(TEST_GRID, TEST_GRID).unwrap(), 0.0);
+
+        // Nearly antipodal doesn't converge, and falls back to haversine
+        let vincenty = grid_distance_vincenty("JJ00", "AI09").unwrap();
+        let haversine = grid_distance("JJ00", "AI09").unwrap();
+        assert_eq!(vincenty, haversine);
+
+        assert!(grid_distance_vincenty("CM87u", "KP04ow").is_err());
+    }
+
+    #[test]
+    fn test_distance_radius() {
+        let dist = grid_distance("CM87um", "KP04ow").unwrap();
+        let same = grid_distance_with_radius("CM87um", "KP04ow", 6371.0).unwrap();
+        assert_eq!(dist, same);
+
+        let double = grid_distance_with_radius("CM87um", "KP04ow", 2.0 * 6371.0).unwrap();
+        assert_delta!(double, 2.0 * dist, 1e-6);
+        let moon = grid_distance_with_radius("CM87um", "KP04ow", 1737.4).unwrap();
+        assert_delta!(moon, dist * 1737.4 / 6371.0, 1e-6);
+
+        assert!(grid_distance_with_radius("CM87u", "KP04ow", 1737.4).is_err());
+    }
+
+    #[test]
+    fn dms() {
+        let (long, lat) = dms_to_longlat("77°02'07.0\"W", "38°53'22.1\"N").unwrap();
+        assert_delta!(long, TEST_LONG, 1e-4);
+        assert_delta!(lat, TEST_LAT, 1e-4);
+        assert_eq!(longlat_to_grid(long, lat, 6).unwrap(), "FM18lv");
+
+        let (long, lat) = dms_to_longlat("77 02 07.0 W", "N 38:53:22.1").unwrap();
+        assert_delta!(long, TEST_LONG, 1e-4);
+        assert_delta!(lat, TEST_LAT, 1e-4);
+        let (long, lat) = dms_to_longlat("-77°2.1167′", "38.889472").unwrap();
+        assert_delta!(long, TEST_LONG, 1e-4);
+        assert_delta!(lat, TEST_LAT, 1e-4);
+
+        // Southern and eastern hemispheres
+        let (long, lat) = dms_to_longlat("151°12'30\"e", "33°51'35\"s").unwrap();
+        assert_delta!(long, 151.208333, 1e-6);
+        assert_delta!(lat, -33.859722, 1e-6);
+
+        // Equator and prime meridian
+        assert_eq!(dms_to_longlat("0°0'0\"E", "0°0'0\"N").unwrap(), (0.0, 0.0));
+        assert_eq!(dms_to_longlat("0", "0").unwrap(), (0.0, 0.0));
+
+        for (long, lat) in [
+            ("77°02'07.0\"N", "38°53'22.1\"N"),
+            ("77°02'07.0\"W", "38°53'22.1\"E"),
+            ("-77°02'07.0\"W", "38°53'22.1\"N"),
+            ("77°60'07.0\"W", "38°53'22.1\"N"),
+            ("77°02'07.0\"W", "98°53'22.1\"N"),
+            ("181", "0"),
+            ("77°02'07'01\"W", "38°53'22.1\"N"),
+            ("77°02'x\"W", "38°53'22.1\"N"),
+            ("inf", "0"),
+            ("", "0"),
+        ] {
+            let ret = dms_to_longlat(long, lat);
+            assert!(
+                matches!(ret, Err(MHError::InvalidDms(_))),
+                "{} {}",
+                long,
+                lat
+            );
+        }
+    }
+
+    #[test]
+    fn to_dms() {
+        let (long, lat) = longlat_to_dms(TEST_LONG, TEST_LAT);
+        assert_eq!(long, "77°02'07.0\"W");
+        assert_eq!(lat, "38°53'22.1\"N");
+
+        let (long, lat) = longlat_to_dms(151.208333, -33.859722);
+        assert_eq!(long, "151°12'30.0\"E");
+        assert_eq!(lat, "33°51'35.0\"S");
+
+        let (long, lat) = longlat_to_dms(0.0, -0.000001);
+        assert_eq!(long, "0°00'00.0\"E");
+        assert_eq!(lat, "0°00'00.0\"N");
+
+        // Rounding carries over into minutes and degrees
+        let (long, _) = longlat_to_dms(-(10.0 + 59.0 / 60.0 + 59.97 / 3600.0), 0.0);
+        assert_eq!(long, "11°00'00.0\"W");
+
+        let (long, lat) = longlat_to_dms(TEST_LONG, TEST_LAT);
+        let (long, lat) = dms_to_longlat(&long, &lat).unwrap();
+        assert_delta!(long, TEST_LONG, 0.1 / 3600.0);
+        assert_delta!(lat, TEST_LAT, 0.1 / 3600.0);
+    }
+
+    #[test]
+    fn typed_precision() {
+        for (n, precision) in [
+            (2, GridPrecision::Field),
+            (4, GridPrecision::Square),
+            (6, GridPrecision::Subsquare),
+            (8, GridPrecision::Extended),
+            (10, GridPrecision::SuperExtended),
+            (12, GridPrecision::HyperExtended),
+        ] {
+            assert_eq!(GridPrecision::try_from(n).unwrap(), precision);
+            assert_eq!(precision as usize, n);
+            assert_eq!(
+                longlat_to_grid_typed(TEST_LONG, TEST_LAT, precision).unwrap(),
+                longlat_to_grid(TEST_LONG, TEST_LAT, n).unwrap()
+            );
+        }
+
+        for n in [0, 1, 3, 5, 11, 14] {
+            let ret = GridPrecision::try_from(n);
+            assert!(matches!(ret, Err(MHError::InvalidPrecision(p)) if p == n));
+        }
+    }
+
+    #[test]
+    fn test_distance_matrix() {
+        let grids = ["CM87um", "KP04ow", TEST_GRID];
+        let matrix = distance_matrix(&grids).unwrap();
+        assert_eq!(matrix.len(), 3);
+        for i in 0..3 {
+            assert_eq!(matrix[i].len(), 3);
+            assert_eq!(matrix[i][i], 0.0);
+            for j in 0..3 {
+                assert_eq!(matrix[i][j], matrix[j][i]);
+                assert_delta!(
+                    matrix[i][j],
+                    grid_distance(grids[i], grids[j]).unwrap(),
+                    1e-9
+                );
+            }
+        }
+
+        assert!(distance_matrix(&[]).unwrap().is_empty());
+        assert!(distance_matrix(&["CM87um", "KP04o"]).is_err());
+    }
+
+    #[test]
+    fn nearest() {
+        let candidates = ["CM87um", "KP04ow", "FM18lv", "FM19"];
+        let (idx, dist) = nearest_grid(TEST_LONG, TEST_LAT, &candidates).unwrap();
+        assert_eq!(idx, 2);
+        assert!(dist < 5.0);
+
+        let (long, lat) = grid_to_longlat("KP04ow").unwrap();
+        let (idx, dist) = nearest_grid(long, lat, &candidates).unwrap();
+        assert_eq!(idx, 1);
+        assert_eq!(dist, 0.0);
+
+        // Closer to FM19 than FM18lv
+        let (idx, _) = nearest_grid(-77.0, 39.6, &candidates).unwrap();
+        assert_eq!(idx, 3);
+
+        assert!(matches!(
+            nearest_grid(TEST_LONG, TEST_LAT, &[]),
+            Err(MHError::NoGrids)
+        ));
+        assert!(matches!(
+            nearest_grid(TEST_LONG, TEST_LAT, &["CM87um", "KP04o"]),
+            Err(MHError::InvalidGridLength(5))
+        ));
+        assert!(nearest_grid(-200.0, TEST_LAT, &candidates).is_err());
+    }
+
+    #[test]
+    fn grid_eq_hash() {
+        let a: Grid = "FM18lv".parse().unwrap();
+        let b: Grid = "fm18LV".parse().unwrap();
+        let c: Grid = "FM18".parse().unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+
+        let mut qsos = alloc::collections::BTreeMap::new();
+        for grid in ["FM18lv", "fm18LV", "Fm18Lv", "FM18", "KP04ow"] {
+            *qsos.entry(grid.parse::<Grid>().unwrap()).or_insert(0) += 1;
+        }
+        assert_eq!(qsos.len(), 3);
+        assert_eq!(qsos[&a], 3);
+        assert_eq!(qsos[&c], 1);
+
+        #[cfg(feature = "std")]
+        {
+            let hashed: std::collections::HashSet<Grid> = [a.clone(), b, c].into_iter().collect();
+            assert_eq!(hashed.len(), 2);
+            assert!(hashed.contains(&a));
+        }
+    }
+
+    #[test]
+    fn rounding_boundaries() {
+        for rounding in [Rounding::Floor, Rounding::Nearest] {
+            // Field and square boundaries are exact, and belong to the cell
+            // to the east/north
+            let grid = longlat_to_grid_rounded(-80.0, 30.0, 4, rounding).unwrap();
+            assert_eq!(grid, "FM00");
+            let grid = longlat_to_grid_rounded(-78.0, 38.0, 6, rounding).unwrap();
+            assert_eq!(grid, "FM18aa");
+            let grid = longlat_to_grid_rounded(0.0, 0.0, 4, rounding).unwrap();
+            assert_eq!(grid, "JJ00");
+            let grid = longlat_to_grid_rounded(-180.0, -90.0, 4, rounding).unwrap();
+            assert_eq!(grid, "AA00");
+        }
+
+        // Subsquare boundaries aren't exact in floating point. Floor gets
+        // this one wrong, while Nearest gets it right.
+        let (long, lat) = grid_southwest_corner("FM18ba").unwrap();
+        assert_eq!(
+            longlat_to_grid_rounded(long, lat, 6, Rounding::Floor).unwrap(),
+            "FM18aa"
+        );
+        assert_eq!(
+            longlat_to_grid_rounded(long, lat, 6, Rounding::Nearest).unwrap(),
+            "FM18ba"
+        );
+
+        // Every corner lands in its own cell with Nearest
+        for precision in [4, 6, 8, 10, 12] {
+            for grid in grids_in_box(-78.0, 38.0, -77.999, 38.001, precision).unwrap() {
+                let (long, lat) = grid_southwest_corner(&grid).unwrap();
+                let ret = longlat_to_grid_rounded(long, lat, precision, Rounding::Nearest);
+                assert_eq!(ret.unwrap(), grid);
+            }
+        }
+
+        // But just inside the cell to the west is still west
+        let ret = longlat_to_grid_rounded(long - 1e-7, lat, 6, Rounding::Nearest);
+        assert_eq!(ret.unwrap(), "FM18aa");
+        // And non-boundary points are the same as longlat_to_grid()
+        let ret = longlat_to_grid_rounded(TEST_LONG, TEST_LAT, 12, Rounding::Nearest);
+        assert_eq!(ret.unwrap(), TEST_GRID);
+
+        assert!(longlat_to_grid_rounded(TEST_LONG, TEST_LAT, 5, Rounding::Nearest).is_err());
+        assert!(longlat_to_grid_rounded(-200.0, TEST_LAT, 6, Rounding::Nearest).is_err());
+    }
+
+    #[test]
+    fn precision_edges() {
+        assert_eq!(longlat_to_grid(180.0, 90.0, 10).unwrap(), "RR99xx99XX");
+        assert_eq!(longlat_to_grid(180.0, 90.0, 12).unwrap(), "RR99xx99XX99");
+        assert_eq!(longlat_to_grid(-180.0, -90.0, 10).unwrap(), "AA00aa00AA");
+        assert_eq!(longlat_to_grid(0.0, 0.0, 10).unwrap(), "JJ00aa00AA");
+        assert_eq!(longlat_to_grid(180.0, -90.0, 6).unwrap(), "RA90xa");
+        assert_eq!(longlat_to_grid(-180.0, 90.0, 6).unwrap(), "AR09ax");
+        assert_eq!(longlat_to_grid(180.0, 0.0, 4).unwrap(), "RJ90");
+
+        // The poles are the edge of the world
+        assert!(longlat_to_grid(0.0, 90.1, 4).is_err());
+        assert!(longlat_to_grid(0.0, -90.1, 4).is_err());
+        assert!(longlat_to_grid(180.1, 0.0, 4).is_err());
+    }
+
+    #[test]
+    fn lenient() {
+        for grid in [
+            "FM 18 lv",
+            "FM-18-LV",
+            "fm.18.lv",
+            " FM18lv\t",
+            "F M 1 8 l v",
+            "FM18-lv.",
+        ] {
+            assert_eq!(parse_grid_lenient(grid).unwrap(), "FM18lv", "{}", grid);
+        }
+        assert_eq!(parse_grid_lenient("FM18 lv53 SL").unwrap(), "FM18lv53SL");
+
+        // Stripping doesn't make invalid grids valid
+        assert!(parse_grid_lenient("FM 18 l").is_err());
+        assert!(parse_grid_lenient("FM_18_lv").is_err());
+        assert!(parse_grid_lenient(" - ").is_err());
+
+        // While the strict parsing stays strict
+        assert!(grid_to_longlat("FM 18 lv").is_err());
+    }
+
+    #[test]
+    fn area() {
+        // All the fields add up to the area of the earth
+        let total: f64 = grids_in_box(-180.0, -90.0, 180.0, 90.0, 2)
+            .unwrap()
+            .map(|g| grid_area_km2(&g).unwrap())
+            .sum();
+        assert_delta!(total, 4.0 * core::f64::consts::PI * RADIUS.powi(2), 1e-3);
+
+        // A square at the equator is about 222 x 111 km
+        let equator = grid_area_km2("JJ00").unwrap();
+        assert_delta!(equator, 222.39 * 111.19, 10.0);
+
+        let polar = grid_area_km2("JR09").unwrap();
+        assert!(equator > polar * 50.0);
+        assert_eq!(grid_area_km2("JA00").unwrap(), polar);
+
+        assert!(grid_area_km2("FM18lv").unwrap() < grid_area_km2("FM18").unwrap());
+        assert!(grid_area_km2("FM1").is_err());
+    }
+
+    #[test]
+    fn utm() {
+        // The CN Tower, from the Wikipedia article on UTM, which gives it as
+        // 17T 630084 4833438. Allow for a meter either way in the rounding.
+        let cn_long = -(79.0 + 23.0 / 60.0 + 13.7 / 3600.0);
+        let cn_lat = 43.0 + 38.0 / 60.0 + 33.24 / 3600.0;
+        let utm = longlat_to_utm(cn_long, cn_lat).unwrap();
+        let parts: Vec<&str> = utm.split(' ').collect();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], "17T");
+        assert!((parts[1].parse::<i64>().unwrap() - 630084).abs() <= 1);
+        assert!((parts[2].parse::<i64>().unwrap() - 4833438).abs() <= 1);
+        let (long, lat) = utm_to_longlat("17T 630084 4833439").unwrap();
+        assert_delta!(long, cn_long, 1e-5);
+        assert_delta!(lat, cn_lat, 1e-5);
+
+        // On the central meridian the easting is 500 km, and the northing is
+        // the scaled meridian arc (1105854.8 m to 10 degrees)
+        assert_eq!(longlat_to_utm(-75.0, 0.0).unwrap(), "18N 500000 0");
+        assert_eq!(longlat_to_utm(-75.0, 10.0).unwrap(), "18P 500000 1105412");
+        assert_eq!(longlat_to_utm(-75.0, -10.0).unwrap(), "18L 500000 8894588");
+        let (long, lat) = utm_to_longlat("18L 500000 8894588").unwrap();
+        assert_delta!(long, -75.0, 1e-9);
+        assert_delta!(lat, -10.0, 1e-5);
+
+        // Eastern hemisphere, and the Norway and Svalbard zone exceptions
+        assert!(longlat_to_utm(151.2, -33.9).unwrap().starts_with("56H "));
+        assert!(longlat_to_utm(5.3, 60.4).unwrap().starts_with("32V "));
+        assert!(longlat_to_utm(15.6, 78.2).unwrap().starts_with("33X "));
+        assert!(longlat_to_utm(180.0, 0.0).unwrap().starts_with("60N "));
+
+        // Pipeline through grids
+        let utm = grid_to_utm("FM18lv").unwrap();
+        assert_eq!(utm_to_grid(&utm, 6).unwrap(), "FM18lv");
+        assert_eq!(utm_to_grid("17 t 630084 4833439", 6).unwrap(), "FN03hp");
+
+        for utm in [
+            "",
+            "17T",
+            "17T 630084",
+            "17T 630084 4833439 1",
+            "61T 630084 4833439",
+            "0T 630084 4833439",
+            "17I 630084 4833439",
+            "17 630084 4833439",
+            "T 630084 4833439",
+            "17T -630084 4833439",
+            "17T 630084 x",
+        ] {
+            let ret = utm_to_longlat(utm);
+            assert!(matches!(ret, Err(MHError::InvalidUtm(_))), "{}", utm);
+        }
+        assert!(longlat_to_utm(0.0, 85.0).is_err());
+        assert!(longlat_to_utm(0.0, -80.5).is_err());
+    }
+
+    #[test]
+    fn detailed() {
+        let loc = grid_to_longlat_detailed("FM18").unwrap();
+        assert_eq!((loc.long, loc.lat), grid_to_longlat("FM18").unwrap());
+        assert_eq!(loc.precision, 4);
+        assert_eq!(loc.cell_width_deg, 2.0);
+        assert_eq!(loc.cell_height_deg, 1.0);
+
+        let loc = grid_to_longlat_detailed("fm18lv").unwrap();
+        assert_eq!(loc.precision, 6);
+        assert_delta!(loc.cell_width_deg, 5.0 / 60.0, 1e-12);
+        assert_delta!(loc.cell_height_deg, 2.5 / 60.0, 1e-12);
+
+        assert!(grid_to_longlat_detailed("FM18l").is_err());
+    }
+
+    #[test]
+    fn grid_from_coords() {
+        let grid = Grid::try_from((TEST_LONG, TEST_LAT)).unwrap();
+        assert_eq!(grid.as_str(), "FM18lv");
+        let grid: Grid = (TEST_LONG, TEST_LAT).try_into().unwrap();
+        assert_eq!(grid.precision(), 6);
+
+        let grid = Grid::from_coords(TEST_LONG, TEST_LAT, 10).unwrap();
+        assert_eq!(grid.as_str(), "FM18lv53SL");
+        assert!(Grid::from_coords(TEST_LONG, TEST_LAT, 5).is_err());
+
+        let ret = Grid::try_from((TEST_LONG, 91.0));
+        assert!(matches!(ret, Err(MHError::InvalidLongLat(_, _))));
+        let ret = Grid::try_from((-181.0, TEST_LAT));
+        assert!(matches!(ret, Err(MHError::InvalidLongLat(_, _))));
+    }
+
+    #[test]
+    fn compass() {
+        for (bearing, point) in [
+            (0.0, "N"),
+            (45.0, "NE"),
+            (90.0, "E"),
+            (135.0, "SE"),
+            (180.0, "S"),
+            (225.0, "SW"),
+            (270.0, "W"),
+            (315.0, "NW"),
+            (22.5, "NNE"),
+            (337.5, "NNW"),
+            (359.9, "N"),
+            (11.2, "N"),
+            (11.25, "NNE"),
+            (348.75, "N"),
+            (348.7, "NNW"),
+        ] {
+            assert_eq!(compass_point(bearing), point, "{}", bearing);
+        }
+
+        assert_eq!(grid_bearing_compass("CM87um", "KP04ow").unwrap(), "NNE");
+        assert_eq!(grid_bearing_compass("KP04ow", "CM87um").unwrap(), "NNW");
+        assert_eq!(grid_bearing_compass("FM18", "FM38").unwrap(), "E");
+        assert!(grid_bearing_compass("FM1", "FM38").is_err());
+    }
+
+    #[test]
+    fn cross_track() {
+        // A point on the path, as close as a 12 character grid gets
+        let path = great_circle_path("CM87um", "KP04ow", 2).unwrap();
+        let mid = longlat_to_grid(path[1].0, path[1].1, 12).unwrap();
+        let dist = cross_track_distance("CM87um", "KP04ow", &mid).unwrap();
+        assert_delta!(dist, 0.0, 0.01);
+
+        // The path endpoints are on it too
+        let dist = cross_track_distance("CM87um", "KP04ow", "KP04ow").unwrap();
+        assert_delta!(dist, 0.0, 1e-6);
+
+        // North of an eastbound path is to the left, south to the right
+        let north = cross_track_distance("JJ00", "JJ90", "JJ51").unwrap();
+        let south = cross_track_distance("JJ00", "JJ90", "JI59").unwrap();
+        assert!(north < -100.0);
+        assert!(south > 100.0);
+        assert!(cross_track_distance("JJ00", "JJ90", "JJ5").is_err());
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn random() {
+        for precision in [2, 4, 6, 8, 10, 12] {
+            for _ in 0..1000 {
+                let grid = random_grid(precision).unwrap();
+                assert_eq!(grid.len(), precision);
+                assert!(grid_to_longlat(&grid).is_ok(), "{}", grid);
+                assert_eq!(normalize_grid(&grid).unwrap(), grid);
+            }
+        }
+        assert!(random_grid(5).is_err());
+
+        // Every field shows up eventually
+        let fields: alloc::collections::BTreeSet<String> =
+            (0..10000).map(|_| random_grid(2).unwrap()).collect();
+        assert_eq!(fields.len(), 18 * 18);
+    }
+
+    #[test]
+    fn antipode() {
+        assert_eq!(grid_antipode("JJ00", 4).unwrap(), "AI09");
+        assert_eq!(grid_antipode("AI09", 4).unwrap(), "JJ00");
+        assert_eq!(grid_antipode("FM18lv", 4).unwrap(), "OF11");
+
+        for grid in ["FM18lv", "KP04ow", "CM87um", "RR99xx", "AA00aa", "JJ00aa"] {
+            let antipode = grid_antipode(grid, 6).unwrap();
+            assert_eq!(grid_antipode(&antipode, 6).unwrap(), grid);
+            assert_delta!(grid_distance(grid, &antipode).unwrap(), 20015.1, 0.1);
+        }
+
+        assert!(grid_antipode("FM18l", 4).is_err());
+        assert!(grid_antipode("FM18lv", 3).is_err());
+    }
+
+    #[test]
+    fn long_path() {
+        let (short, short_bearing) = grid_dist_bearing("CM87um", "KP04ow").unwrap();
+        let (long, long_bearing) = grid_long_path("CM87um", "KP04ow").unwrap();
+        assert_delta!(short + long, 40030.2, 0.1);
+        assert_delta!(long_bearing, (short_bearing + 180.0) % 360.0, 1e-9);
+        assert!(long > short);
+
+        assert!(grid_long_path("CM87um", "KP04o").is_err());
+    }
+
+    #[test]
+    fn invalid_grid_position() {
+        for (grid, pos, ch) in [
+            ("SA00", 0, 'S'),
+            ("AS00", 1, 'S'),
+            ("ST00", 0, 'S'),
+            ("RZ00", 1, 'Z'),
+            ("AA0a", 3, 'a'),
+            ("AA00ay00", 5, 'y'),
+            ("FM18lv5x", 7, 'x'),
+        ] {
+            match grid_to_longlat(grid) {
+                Err(MHError::InvalidGridChar {
+                    grid: g,
+                    position,
+                    found,
+                }) => {
+                    assert_eq!(g, grid);
+                    assert_eq!(position, pos, "{}", grid);
+                    assert_eq!(found, ch, "{}", grid);
+                }
+                ret => panic!("{}: {:?}", grid, ret),
+            }
+        }
+
+        // The first offending position is the one reported
+        let err = grid_to_longlat("ZZ9").unwrap_err();
+        assert!(matches!(err, MHError::InvalidGridChar { position: 0, .. }));
+        assert_eq!(
+            err.to_string(),
+            "Invalid character `Z` at position 0 in grid `ZZ9`, expected a letter A-R"
+        );
+        assert_eq!(
+            grid_to_longlat("FM18l5").unwrap_err().to_string(),
+            "Invalid character `5` at position 5 in grid `FM18l5`, expected a letter A-X"
+        );
+    }
+
+    #[test]
+    fn latlong_parse() {
+        let pos: LatLong = TEST_GRID[0..6].parse().unwrap();
+        let (long, lat) = grid_to_longlat(&TEST_GRID[0..6]).unwrap();
+        assert_eq!(pos, LatLong { lat, long });
+        assert_eq!(pos.to_grid(6).unwrap(), TEST_GRID[0..6]);
+
+        let pos: LatLong = "38.889484, -77.035278".parse().unwrap();
+        assert_delta!(pos.lat, TEST_LAT, 1e-9);
+        assert_delta!(pos.long, TEST_LONG, 1e-9);
+        assert_eq!(pos.to_grid(8).unwrap(), TEST_GRID[0..8]);
+        let pos: LatLong = " -33.5,151 ".parse().unwrap();
+        assert_eq!(
+            pos,
+            LatLong {
+                lat: -33.5,
+                long: 151.0
+            }
+        );
+
+        for garbage in ["", "hello", "38.8", "38.8,", "a,b", "1,2,3", "FM18l"] {
+            let ret = garbage.parse::<LatLong>();
+            assert!(matches!(ret, Err(MHError::InvalidGrid(_))), "{}", garbage);
+        }
+        assert!(matches!(
+            "91,0".parse::<LatLong>(),
+            Err(MHError::InvalidLongLat(_, _))
+        ));
+    }
+
+    #[test]
+    fn along_path() {
+        // Due north along a column of squares, crossing into the next field
+        let grids = grids_along_path("FN08aa", "FO00ax", 4).unwrap();
+        assert_eq!(grids, ["FN08", "FN09", "FO00"]);
+        let grids = grids_along_path("FN08aa", "FO00ax", 6).unwrap();
+        assert_eq!(grids.len(), 24 * 3);
+        assert_eq!(grids[47..49], ["FN09ax", "FO00aa"]);
+
+        // Crossing the antimeridian and the equator
+        let grids = grids_along_path("RJ09", "AI90", 4).unwrap();
+        assert_eq!(grids.first().unwrap(), "RJ09");
+        assert_eq!(grids.last().unwrap(), "AI90");
+        for pair in grids.windows(2) {
+            let neighbors = grid_neighbors(&pair[0]).unwrap();
+            assert!(neighbors.contains(&pair[1]), "{:?}", pair);
+        }
+
+        // East-west close to the north pole, where the grids are much narrower
+        // than they are high
+        let grids = grids_along_path("JR06", "KR06", 4).unwrap();
+        assert_eq!(
+            grids,
+            [
+                "JR06", "JR16", "JR26", "JR36", "JR46", "JR56", "JR66", "JR76", "JR86", "JR96",
+                "KR06"
+            ]
+        );
+        for (from, to) in [
+            ("JR05mm", "JR15mm"),
+            ("JR09ax", "KR09xx"),
+            ("AA00ab", "BA90aa"),
+        ] {
+            let grids = grids_along_path(from, to, 6).unwrap();
+            assert_eq!(grids.first().unwrap(), from);
+            assert_eq!(grids.last().unwrap(), to);
+            for pair in grids.windows(2) {
+                assert!(grids_adjacent(&pair[0], &pair[1]).unwrap(), "{:?}", pair);
+            }
+        }
+        assert_eq!(grids_along_path("JR05mm", "JR15mm", 6).unwrap().len(), 25);
+
+        // Straight over the pole, from one side of the world to the other
+        let grids = grids_along_path("JR00", "AR00", 4).unwrap();
+        assert_eq!(grids.len(), 20);
+        assert_eq!(grids[9..11], ["JR09", "AR09"]);
+
+        assert_eq!(grids_along_path("FN05aa", "FN05ab", 4).unwrap(), ["FN05"]);
+        assert!(grids_along_path("FN05aa", "FN25xa", 5).is_err());
+        assert!(grids_along_path("KP04ow", "BC05ob", 4).is_err());
+    }
+
+    #[test]
+    fn longlat_const() {
+        for (long, lat) in [
+            (TEST_LONG, TEST_LAT),
+            (0.0, 0.0),
+            (-180.0, -90.0),
+            (180.0, 90.0),
+            (151.2, -33.9),
+        ] {
+            assert_eq!(
+                longlat_to_grid_const::<2>(long, lat).unwrap(),
+                longlat_to_grid(long, lat, 2).unwrap()
+            );
+            assert_eq!(
+                longlat_to_grid_const::<4>(long, lat).unwrap(),
+                longlat_to_grid(long, lat, 4).unwrap()
+            );
+            assert_eq!(
+                longlat_to_grid_const::<6>(long, lat).unwrap(),
+                longlat_to_grid(long, lat, 6).unwrap()
+            );
+            assert_eq!(
+                longlat_to_grid_const::<8>(long, lat).unwrap(),
+                longlat_to_grid(long, lat, 8).unwrap()
+            );
+            assert_eq!(
+                longlat_to_grid_const::<10>(long, lat).unwrap(),
+                longlat_to_grid(long, lat, 10).unwrap()
+            );
+            assert_eq!(
+                longlat_to_grid_const::<12>(long, lat).unwrap(),
+                longlat_to_grid(long, lat, 12).unwrap()
+            );
+        }
+        assert_eq!(
+            longlat_to_grid_const::<12>(TEST_LONG, TEST_LAT).unwrap(),
+            TEST_GRID
+        );
+        assert!(longlat_to_grid_const::<6>(181.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn longlat_buf() {
+        // Exact size
+        let mut buf = [0u8; 12];
+        let grid = longlat_to_grid_buf(TEST_LONG, TEST_LAT, 12, &mut buf).unwrap();
+        assert_eq!(grid, TEST_GRID);
+
+        // Oversize, only the start of the buffer is used
+        let mut buf = [b'-'; 16];
+        let grid = longlat_to_grid_buf(TEST_LONG, TEST_LAT, 6, &mut buf).unwrap();
+        assert_eq!(grid, &TEST_GRID[0..6]);
+        assert_eq!(&buf[6..], b"----------");
+
+        // Undersize
+        let mut buf = [0u8; 5];
+        let ret = longlat_to_grid_buf(TEST_LONG, TEST_LAT, 6, &mut buf);
+        assert!(matches!(ret, Err(MHError::BufferTooSmall(5, 6))));
+
+        let mut buf = [0u8; 12];
+        assert!(longlat_to_grid_buf(TEST_LONG, TEST_LAT, 7, &mut buf).is_err());
+        assert!(longlat_to_grid_buf(TEST_LONG, 91.0, 6, &mut buf).is_err());
+    }
+
+    #[test]
+    fn refinements() {
+        let grids: Vec<String> = grid_refinements(TEST_LONG, TEST_LAT).unwrap().collect();
+        assert_eq!(grids.len(), 6);
+        assert_eq!(grids.last().unwrap(), TEST_GRID);
+        for pair in grids.windows(2) {
+            assert!(pair[1].starts_with(&pair[0]), "{:?}", pair);
+            assert_eq!(pair[1].len(), pair[0].len() + 2);
+        }
+
+        for (long, lat) in [(0.0, 0.0), (180.0, 90.0), (-180.0, -90.0), (151.2, -33.9)] {
+            for (i, grid) in grid_refinements(long, lat).unwrap().enumerate() {
+                assert_eq!(grid, longlat_to_grid(long, lat, (i + 1) * 2).unwrap());
+            }
+        }
+
+        assert!(grid_refinements(0.0, 90.1).is_err());
+    }
+
+    #[test]
+    fn longlat_non_finite() {
+        for bad in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            for (long, lat) in [(bad, 0.0), (0.0, bad), (bad, bad)] {
+                for precision in [2, 6, 12] {
+                    let ret = longlat_to_grid(long, lat, precision);
+                    assert!(
+                        matches!(ret, Err(MHError::InvalidLongLat(_, _))),
+                        "{} {}",
+                        long,
+                        lat
+                    );
+                }
+                assert!(longlat_to_grid_const::<6>(long, lat).is_err());
+                assert!(grid_refinements(long, lat).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn level() {
+        for (len, level) in [
+            (2, GridPrecision::Field),
+            (4, GridPrecision::Square),
+            (6, GridPrecision::Subsquare),
+            (8, GridPrecision::Extended),
+            (10, GridPrecision::SuperExtended),
+            (12, GridPrecision::HyperExtended),
+        ] {
+            assert_eq!(grid_level(&TEST_GRID[0..len]).unwrap(), level);
+        }
+
+        for len in [1, 3, 5, 7, 9, 11] {
+            let ret = grid_level(&TEST_GRID[0..len]);
+            assert!(matches!(ret, Err(MHError::InvalidGridLength(l)) if l == len));
+        }
+        assert!(grid_level("").is_err());
+        assert!(grid_level("FM18zz").is_err());
+    }
+
+    #[test]
+    fn adjacent() {
+        // Horizontally, vertically and diagonally
+        assert!(grids_adjacent("FN31pr", "FN31qr").unwrap());
+        assert!(grids_adjacent("FN31pr", "FN31ps").unwrap());
+        assert!(grids_adjacent("FN31pr", "FN31oq").unwrap());
+        assert!(grids_adjacent("FN31", "FN42").unwrap());
+        // Regardless of casing
+        assert!(grids_adjacent("fn31PR", "FN31qr").unwrap());
+
+        // Not adjacent
+        assert!(!grids_adjacent("FN31pr", "FN31rr").unwrap());
+        assert!(!grids_adjacent("FN31pr", "FN31pr").unwrap());
+        assert!(!grids_adjacent("FN31", "FN33").unwrap());
+
+        // Across the antimeridian
+        assert!(grids_adjacent("RJ99", "AK00").unwrap());
+        assert!(grids_adjacent("AI09", "RI99").unwrap());
+
+        // Next to the poles
+        assert!(grids_adjacent("JR09", "JR19").unwrap());
+        assert!(grids_adjacent("JR09", "JR08").unwrap());
+        assert!(!grids_adjacent("JR09", "JA00").unwrap());
+        assert!(grids_adjacent("AA00", "RA90").unwrap());
+
+        assert!(matches!(
+            grids_adjacent("FN31pr", "FN31"),
+            Err(MHError::PrecisionMismatch(6, 4))
+        ));
+        assert!(grids_adjacent("FN31pr", "FN31zz").is_err());
+    }
+
+    #[test]
+    fn bearing_vincenty() {
+        // Within a degree of the spherical bearing over a mid range path
+        let vincenty = grid_bearing_vincenty("CM87um", "FN20xr").unwrap();
+        let haversine = grid_bearing("CM87um", "FN20xr").unwrap();
+        assert!(vincenty != haversine);
+        assert_delta!(vincenty, haversine, 1.0);
+
+        let (dist, bearing) = grid_dist_bearing_vincenty("CM87um", "FN20xr").unwrap();
+        assert_eq!(dist, grid_distance_vincenty("CM87um", "FN20xr").unwrap());
+        assert_eq!(bearing, vincenty);
+
+        // Nearly antipodal points fall back to the spherical bearing
+        let vincenty = grid_bearing_vincenty("JJ00", "AI09").unwrap();
+        assert_eq!(vincenty, grid_bearing("JJ00", "AI09").unwrap());
+
+        assert!(grid_bearing_vincenty("CM87u", "KP04ow").is_err());
+    }
+
+    #[test]
+    fn uncertainty() {
+        let mut last = f64::MAX;
+        for len in (2..=12).step_by(2) {
+            let km = grid_uncertainty_km(&TEST_GRID[0..len]).unwrap();
+            assert!(km < last, "{}", len);
+            last = km;
+        }
+
+        // A square on the equator is 2 by 1 degrees
+        let km_per_deg = RADIUS * core::f64::consts::PI / 180.0;
+        assert_delta!(
+            grid_uncertainty_km("JJ00").unwrap(),
+            5.0f64.sqrt() / 2.0 * km_per_deg,
+            1.0
+        );
+        // And narrower further north
+        assert!(grid_uncertainty_km("JP00").unwrap() < grid_uncertainty_km("JJ00").unwrap());
+
+        assert!(grid_uncertainty_km("JJ0").is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn io_error() {
+        fn parse(grid: &str) -> std::io::Result<(f64, f64)> {
+            Ok(grid_to_longlat(grid)?)
+        }
+
+        let mh = grid_to_longlat("FM18l").unwrap_err();
+        let io = parse("FM18l").unwrap_err();
+        assert_eq!(io.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(io.to_string(), mh.to_string());
+        assert!(parse("FM18lv").is_ok());
+    }
+
+    #[cfg(feature = "geo")]
+    #[test]
+    fn geo() {
+        let point = grid_to_point(TEST_GRID).unwrap();
+        let (long, lat) = grid_to_longlat(TEST_GRID).unwrap();
+        assert_eq!(point, geo_types::Point::new(long, lat));
+        assert_eq!(point_to_grid(point, 12).unwrap(), TEST_GRID);
+        let point = geo_types::Point::new(TEST_LONG, TEST_LAT);
+        assert_eq!(point_to_grid(point, 6).unwrap(), TEST_GRID[0..6]);
+        assert!(point_to_grid(geo_types::Point::new(0.0, 91.0), 6).is_err());
+
+        let rect = grid_to_rect("FM18").unwrap();
+        assert_eq!(rect.min(), geo_types::coord! { x: -78.0, y: 38.0 });
+        assert_eq!(rect.max(), geo_types::coord! { x: -76.0, y: 39.0 });
+        assert_eq!(rect.center(), grid_to_point("FM18").unwrap().0);
+
+        assert!(grid_to_point("FM18l").is_err());
+        assert!(grid_to_rect("FM18l").is_err());
+    }
+
+    #[test]
+    fn all() {
+        let grids: Vec<String> = all_grids(2).unwrap().collect();
+        assert_eq!(grids.len(), 324);
+        assert_eq!(grids.first().unwrap(), "AA");
+        assert_eq!(grids.last().unwrap(), "RR");
+        let unique: alloc::collections::BTreeSet<&String> = grids.iter().collect();
+        assert_eq!(unique.len(), grids.len());
+        assert!(grids.iter().all(|g| validate_grid(g).is_ok()));
+
+        assert_eq!(all_grids(4).unwrap().count(), 32400);
+        assert_eq!(all_grids(12).unwrap().nth(1).unwrap(), "AA00aa00AA10");
+        assert!(all_grids(3).is_err());
+    }
+
+    #[test]
+    fn subsquare_case() {
+        // The subsquare range is checked regardless of case
+        let upper = grid_to_longlat("FM18LV").unwrap();
+        let lower = grid_to_longlat("FM18lv").unwrap();
+        assert_eq!(upper, lower);
+        assert_eq!(grid_to_longlat("FM18Lv").unwrap(), lower);
+        assert_eq!(normalize_grid("FM18LV").unwrap(), "FM18lv");
+        assert!(grid_to_longlat("FM18XX").is_ok());
+        assert!(grid_to_longlat("FM18xx").is_ok());
+
+        for grid in ["FM18LY", "FM18ly", "FM18ZV", "FM18zv"] {
+            let ret = grid_to_longlat(grid);
+            assert!(
+                matches!(ret, Err(MHError::InvalidGridChar { .. })),
+                "{}",
+                grid
+            );
+        }
+    }
+
+    #[test]
+    fn coord_dist_and_bearing() {
+        let from = grid_to_longlat("CM87um").unwrap();
+        let to = grid_to_longlat("KP04ow").unwrap();
+        let (dist, bearing) = grid_dist_bearing("CM87um", "KP04ow").unwrap();
+        assert_eq!(coord_distance(from, to).unwrap(), dist);
+        assert_eq!(coord_bearing(from, to).unwrap(), bearing);
+        for (from, to) in [("CM87um", "KP04ow"), ("FN20xr", "PM95"), ("RI99", "AI09")] {
+            let coords =
+                coord_dist_bearing(grid_to_longlat(from).unwrap(), grid_to_longlat(to).unwrap());
+            assert_eq!(coords.unwrap(), grid_dist_bearing(from, to).unwrap());
+        }
+
+        assert_delta!(
+            coord_distance((0.0, 0.0), (0.0, 90.0)).unwrap(),
+            10007.5,
+            0.1
+        );
+        assert_eq!(coord_bearing((0.0, 0.0), (90.0, 0.0)).unwrap(), 90.0);
+
+        assert!(coord_distance((181.0, 0.0), (0.0, 0.0)).is_err());
+        assert!(coord_distance((0.0, 0.0), (0.0, -91.0)).is_err());
+        assert!(coord_bearing((f64::NAN, 0.0), (0.0, 0.0)).is_err());
+        assert!(coord_dist_bearing((0.0, 0.0), (180.5, 0.0)).is_err());
+        assert!(coord_dist_bearing((0.0, -90.5), (0.0, 0.0)).is_err());
+    }
+
+    #[test]
+    fn route_distance() {
+        let route = ["CM87um", "DM04md", "FN20xr"];
+        let manual =
+            grid_distance(route[0], route[1]).unwrap() + grid_distance(route[1], route[2]).unwrap();
+        assert_delta!(path_distance(&route).unwrap(), manual, 1e-9);
+
+        assert_eq!(path_distance(&[]).unwrap(), 0.0);
+        assert_eq!(path_distance(&["CM87um"]).unwrap(), 0.0);
+
+        let ret = path_distance(&["CM87um", "DM04md", "FN20x"]);
+        match ret {
+            Err(MHError::InvalidPathGrid(2, e)) => {
+                assert!(matches!(*e, MHError::InvalidGridLength(5)))
+            }
+            ret => panic!("{:?}", ret),
+        }
+    }
+
+    #[test]
+    fn latlong_format() {
+        assert_eq!(format_latlong(TEST_LONG, TEST_LAT), "38.8895°N, 77.0353°W");
+        assert_eq!(
+            format_latlong(151.20929, -33.86882),
+            "33.8688°S, 151.2093°E"
+        );
+        assert_eq!(format_latlong(-58.3816, -34.6037), "34.6037°S, 58.3816°W");
+        assert_eq!(format_latlong(139.6917, 35.6895), "35.6895°N, 139.6917°E");
+
+        // The equator and prime meridian
+        assert_eq!(format_latlong(0.0, 0.0), "0.0000°N, 0.0000°E");
+        assert_eq!(format_latlong(-0.00001, -0.00001), "0.0000°N, 0.0000°E");
+        assert_eq!(format_latlong(-180.0, -90.0), "90.0000°S, 180.0000°W");
+    }
+
+    #[test]
+    fn superextended_range() {
+        // Positions 8 and 9 are A-X like the subsquare
+        for (grid, pos) in [("FM18lv53YL", 8), ("FM18lv53SY", 9), ("FM18lv53zl", 8)] {
+            let ret = grid_to_longlat(grid);
+            assert!(
+                matches!(ret, Err(MHError::InvalidGridChar { position, .. }) if position == pos),
+                "{}",
+                grid
+            );
+        }
+        assert!(grid_to_longlat("FM18lv53XX").is_ok());
+        assert!(grid_to_longlat("FM18lv53xx").is_ok());
+        assert!(grid_to_longlat("FM18lv5A").is_err());
+    }
+
+    #[test]
+    fn in_cell() {
+        // South west corner and center of FM18
+        assert_eq!(coord_in_cell(-78.0, 38.0, 4).unwrap(), (0.0, 0.0));
+        assert_eq!(coord_in_cell(-77.0, 38.5, 4).unwrap(), (0.5, 0.5));
+        let (long, lat) = grid_to_longlat("FM18lv").unwrap();
+        let (x, y) = coord_in_cell(long, lat, 6).unwrap();
+        assert_delta!(x, 0.5, 1e-9);
+        assert_delta!(y, 0.5, 1e-9);
+
+        let (x, y) = coord_in_cell(TEST_LONG, TEST_LAT, 8).unwrap();
+        assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+        assert_eq!(coord_in_cell(180.0, 90.0, 6).unwrap(), (1.0, 1.0));
+
+        // Right at an edge, where float rounding puts the coordinate at the
+        // very east side of the cell rather than the west side of the next
+        let (long, lat) = (-67.50625000000001, -33.754375);
+        assert_eq!(longlat_to_grid(long, lat, 10).unwrap(), "FF66ff98FW");
+        let (x, _) = coord_in_cell(long, lat, 10).unwrap();
+        assert!(x > 0.999, "{}", x);
+
+        assert!(coord_in_cell(0.0, 0.0, 5).is_err());
+        assert!(coord_in_cell(0.0, 90.5, 4).is_err());
+    }
+
+    #[test]
+    fn clamped() {
+        assert_eq!(
+            longlat_to_grid_clamped(TEST_LONG, 90.0001, 6).unwrap(),
+            "FR19lx"
+        );
+        assert_eq!(
+            longlat_to_grid_clamped(TEST_LONG, 90.0001, 6).unwrap(),
+            longlat_to_grid(TEST_LONG, 90.0, 6).unwrap()
+        );
+        assert_eq!(longlat_to_grid_clamped(-180.5, -95.0, 4).unwrap(), "AA00");
+        assert_eq!(
+            longlat_to_grid_clamped(f64::INFINITY, 0.0, 2).unwrap(),
+            "RJ"
+        );
+        assert_eq!(
+            longlat_to_grid_clamped(TEST_LONG, TEST_LAT, 12).unwrap(),
+            TEST_GRID
+        );
+
+        assert!(longlat_to_grid_clamped(0.0, 91.0, 7).is_err());
+        assert!(longlat_to_grid_clamped(f64::NAN, 0.0, 6).is_err());
+    }
+
+    #[test]
+    fn destination() {
+        for (from, dist, bearing) in [
+            ("FN20xr", 300.0, 45.0),
+            ("JO65df", 1234.0, 200.0),
+            ("RI99", 500.0, 90.0),
+            ("PM95", 8000.0, 330.0),
+        ] {
+            let to = destination_grid(from, dist, bearing, 10).unwrap();
+            let (back_dist, back_bearing) = grid_dist_bearing(from, &to).unwrap();
+            assert_delta!(back_dist, dist, 0.1);
+            assert_delta!(back_bearing, bearing, 0.1);
+        }
+
+        // Across the antimeridian
+        assert!(destination_grid("RI99", 500.0, 90.0, 4)
+            .unwrap()
+            .starts_with("AI"));
+        assert_eq!(destination_grid("FN20xr", 0.0, 123.0, 6).unwrap(), "FN20xr");
+
+        assert!(destination_grid("FN20x", 300.0, 45.0, 6).is_err());
+        assert!(destination_grid("FN20xr", 300.0, 45.0, 5).is_err());
+        assert!(destination_grid("FN20xr", f64::NAN, 45.0, 6).is_err());
+    }
+
+    #[test]
+    fn truncate() {
+        assert_eq!(grid_truncate("FM18lv53SL", 6).unwrap(), "FM18lv");
+        assert_eq!(grid_truncate("fm18LV53sl", 4).unwrap(), "FM18");
+        assert_eq!(grid_truncate("FM18lv", 6).unwrap(), "FM18lv");
+        assert_eq!(grid_truncate(TEST_GRID, 2).unwrap(), "FM");
+        assert!(grid_contains(&grid_truncate(TEST_GRID, 8).unwrap(), TEST_GRID).unwrap());
+
+        assert!(matches!(
+            grid_truncate("FM18lv", 10),
+            Err(MHError::InvalidPrecision(10))
+        ));
+        assert!(grid_truncate("FM18lv", 3).is_err());
+        assert!(grid_truncate("FM18lv", 0).is_err());
+        assert!(grid_truncate("FM18l", 2).is_err());
+    }
+
+    #[test]
+    fn roundtrip() {
+        for precision in [2, 4] {
+            for grid in all_grids(precision).unwrap() {
+                assert_roundtrip(&grid).unwrap();
+            }
+        }
+        // The corners of the world at every precision
+        for grid in [
+            "RR09",
+            "RR99xx99XX99",
+            "AA00aa00AA00",
+            "RA90xa90XA90",
+            "AR09ax09AX09",
+        ] {
+            for len in (2..=grid.len()).step_by(2) {
+                assert_roundtrip(&grid[..len]).unwrap();
+            }
+        }
+        assert_roundtrip("fm18LV").unwrap();
+        assert!(assert_roundtrip("FM18l").is_err());
+    }
+
+    #[test]
+    fn grid_with_error() {
+        let mut last = f64::MAX;
+        for precision in [2, 4, 6, 8, 10, 12] {
+            let (grid, error) = longlat_to_grid_with_error(TEST_LONG, TEST_LAT, precision).unwrap();
+            assert_eq!(grid, TEST_GRID[..precision]);
+            assert!(error < last, "{}", precision);
+            assert!(error <= grid_uncertainty_km(&grid).unwrap());
+            last = error;
+        }
+
+        // No error at the center of a grid
+        let (long, lat) = grid_to_longlat("FM18lv").unwrap();
+        let (_, error) = longlat_to_grid_with_error(long, lat, 6).unwrap();
+        assert_delta!(error, 0.0, 1e-9);
+
+        assert!(longlat_to_grid_with_error(TEST_LONG, TEST_LAT, 5).is_err());
+    }
+
+    #[test]
+    fn valid_grid() {
+        // The same cases as longlat_invalid and longlat_invalid_range
+        for grid in [
+            "AI021",
+            "AIA2",
+            "🤷I00",
+            "AA00AA00AA00AA",
+            "AA00AA00AA0A",
+            "SA00",
+            "AS00",
+            "sa00",
+            "AA00ya00",
+            "AA00ay00",
+            "AA00aa00YA",
+            "AA00aa00AY",
+            "ZZ99",
+            "",
+        ] {
+            assert!(!is_valid_grid(grid), "{}", grid);
+            assert!(grid_to_longlat(grid).is_err(), "{}", grid);
+        }
+
+        for grid in [
+            "AA00AA00AA00",
+            "AA00AA00AA",
+            "RR99xx99XX",
+            "rr99XX99xx",
+            "FM",
+            TEST_GRID,
+        ] {
+            assert!(is_valid_grid(grid), "{}", grid);
+            assert!(grid_to_longlat(grid).is_ok(), "{}", grid);
+        }
+    }
+
+    #[test]
+    fn adif() {
+        assert_eq!(
+            grid_from_adif_field("<GRIDSQUARE:6>FM18lv").unwrap(),
+            "FM18lv"
+        );
+        assert_eq!(grid_from_adif_field("<gridsquare:4>fm18").unwrap(), "FM18");
+        assert_eq!(
+            grid_from_adif_field("<MY_GRIDSQUARE:8:S>fm18LV53").unwrap(),
+            "FM18lv53"
+        );
+        // Only the given length is the data
+        assert_eq!(
+            grid_from_adif_field("  <GRIDSQUARE:4>FM18 <EOR>").unwrap(),
+            "FM18"
+        );
+        assert_eq!(
+            grid_from_adif_field("<GRIDSQUARE:4>FM18lv").unwrap(),
+            "FM18"
+        );
+
+        for field in [
+            "",
+            "FM18lv",
+            "<GRIDSQUARE:6 FM18lv",
+            "<GRIDSQUARE>FM18lv",
+            "<GRIDSQUARE:x>FM18lv",
+            "<GRIDSQUARE:8>FM18lv",
+            "<GRIDSQUARE:6:S:X>FM18lv",
+            "<CALL:6>FM18lv",
+        ] {
+            let ret = grid_from_adif_field(field);
+            assert!(matches!(ret, Err(MHError::InvalidAdif(_))), "{}", field);
+        }
+
+        // A well formed field with an invalid grid
+        let ret = grid_from_adif_field("<GRIDSQUARE:6>FM18zz");
+        assert!(matches!(ret, Err(MHError::InvalidGridChar { .. })));
+        let ret = grid_from_adif_field("<GRIDSQUARE:5>FM18l");
+        assert!(matches!(ret, Err(MHError::InvalidGridLength(5))));
+    }
+
+    #[test]
+    fn subcell() {
+        assert_eq!(subcell_index(TEST_LONG, TEST_LAT, 2).unwrap(), (1, 8));
+        assert_eq!(subcell_index(TEST_LONG, TEST_LAT, 4).unwrap(), (11, 21));
+        assert_eq!(subcell_index(TEST_LONG, TEST_LAT, 6).unwrap(), (5, 3));
+
+        for (long, lat) in [
+            (TEST_LONG, TEST_LAT),
+            (180.0, 90.0),
+            (-180.0, -90.0),
+            (151.2, -33.9),
+        ] {
+            for parent in [2, 4, 6, 8, 10] {
+                let (col, row) = subcell_index(long, lat, parent).unwrap();
+                let cells = LEVEL_CELLS[parent / 2] as u32;
+                assert!(col < cells && row < cells, "{} {} {}", long, lat, parent);
+            }
+        }
+        assert_eq!(subcell_index(180.0, 90.0, 4).unwrap(), (23, 23));
+
+        assert!(subcell_index(TEST_LONG, TEST_LAT, 12).is_err());
+        assert!(subcell_index(TEST_LONG, TEST_LAT, 3).is_err());
+        assert!(subcell_index(TEST_LONG, 91.0, 4).is_err());
+    }
+
+    #[test]
+    fn grid_order() {
+        let grid = |g: &str| g.parse::<Grid>().unwrap();
+        assert!(grid("AA") < grid("BA"));
+        assert!(grid("BA") < grid("AB"));
+        assert!(grid("AB") < grid("BB"));
+        assert!(grid("FM18") < grid("FM18aa"));
+        assert!(grid("FM18aa") < grid("FM18ab"));
+        assert_eq!(
+            grid("fm18LV").cmp(&grid("FM18lv")),
+            core::cmp::Ordering::Equal
+        );
+
+        // Neighbors sort much closer to each other than to a far away grid
+        let mut sorted: Vec<Grid> = all_grids(4).unwrap().map(|g| grid(&g)).collect();
+        sorted.sort();
+        let rank: alloc::collections::BTreeMap<&str, i64> = sorted
+            .iter()
+            .enumerate()
+            .map(|(i, g)| (g.as_str(), i as i64))
+            .collect();
+        let mut closer = 0;
+        for g in &sorted {
+            let east = step_grid(g.as_str(), 1, 0).unwrap();
+            let antipode = grid_antipode(g.as_str(), 4).unwrap();
+            let near = (rank[g.as_str()] - rank[east.as_str()]).abs();
+            let far = (rank[g.as_str()] - rank[antipode.as_str()]).abs();
+            if near < far {
+                closer += 1;
+            }
+        }
+        assert!(closer > sorted.len() * 9 / 10, "{}", closer);
+    }
+
+    #[test]
+    fn common() {
+        assert_eq!(common_grid("FM18lv", "FM18ab").unwrap(), "FM18");
+        assert_eq!(common_grid("FM18lv", "fm18LW").unwrap(), "FM18");
+        assert_eq!(common_grid("FM18lv53", "FM18lv").unwrap(), "FM18lv");
+        assert_eq!(common_grid("FM18lv", "FM18lv").unwrap(), "FM18lv");
+        // A shared character that isn't a whole pair doesn't count
+        assert_eq!(common_grid("FM18", "FM19").unwrap(), "FM");
+
+        assert!(matches!(
+            common_grid("FM18", "EM18"),
+            Err(MHError::NoCommonGrid(_, _))
+        ));
+        assert!(common_grid("FM18", "FN18").is_err());
+        assert!(common_grid("FM18", "FM1").is_err());
+    }
+
+    #[test]
+    fn geojson() {
+        let json = grid_to_geojson("fm18").unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"Feature","geometry":{"type":"Polygon","coordinates":[[[-78,38],[-76,38],[-76,39],[-78,39],[-78,38]]]},"properties":{"grid":"FM18"}}"#
+        );
+
+        let value: serde_json::Value =
+            serde_json::from_str(&grid_to_geojson(TEST_GRID).unwrap()).unwrap();
+        assert_eq!(value["type"], "Feature");
+        assert_eq!(value["geometry"]["type"], "Polygon");
+        assert_eq!(value["properties"]["grid"], TEST_GRID);
+        let ring = value["geometry"]["coordinates"][0].as_array().unwrap();
+        assert_eq!(ring.len(), 5);
+        assert_eq!(ring[0], ring[4]);
+
+        assert!(grid_to_geojson("FM18l").is_err());
+    }
+
+    #[test]
+    fn truncating() {
+        assert_eq!(parse_grid_truncating("FM18l").unwrap(), "FM18");
+        assert_eq!(parse_grid_truncating("fm18LV5").unwrap(), "FM18lv");
+        assert_eq!(parse_grid_truncating("FM18lv").unwrap(), "FM18lv");
+        assert_eq!(
+            parse_grid_truncating("FM18lv53SL345").unwrap(),
+            "FM18lv53SL34"
+        );
+        // The dangling character is dropped without looking at it
+        assert_eq!(parse_grid_truncating("FM18?").unwrap(), "FM18");
+
+        assert!(matches!(
+            parse_grid_truncating("F"),
+            Err(MHError::InvalidGridLength(1))
+        ));
+        // Same for a single multibyte character
+        assert!(matches!(
+            parse_grid_truncating("é"),
+            Err(MHError::InvalidGridChar {
+                position: 0,
+                found: 'é',
+                ..
+            })
+        ));
+        assert_eq!(parse_grid_truncating("FM18é").unwrap(), "FM18");
+        assert!(parse_grid_truncating("").is_err());
+        assert!(parse_grid_truncating("FM1zl").is_err());
+        // The strict parsing is unchanged
+        assert!(normalize_grid("FM18l").is_err());
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(cell_size(2).unwrap(), (20.0, 10.0));
+        assert_eq!(cell_size(4).unwrap(), (2.0, 1.0));
+        assert_eq!(cell_size(6).unwrap(), (5.0 / 60.0, 2.5 / 60.0));
+        for precision in [2, 4, 6, 8, 10, 12] {
+            let loc = grid_to_longlat_detailed(&TEST_GRID[..precision]).unwrap();
+            assert_eq!(
+                cell_size(precision).unwrap(),
+                (loc.cell_width_deg, loc.cell_height_deg)
+            );
+        }
+        assert!(cell_size(0).is_err());
+        assert!(cell_size(5).is_err());
+        assert!(cell_size(14).is_err());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn matrix_parallel() {
+        let grids = ["CM87um", "KP04ow", "FN20xr", "PM95", "JJ00", "RR99xx"];
+        assert_eq!(
+            distance_matrix_parallel(&grids).unwrap(),
+            distance_matrix(&grids).unwrap()
+        );
+        assert!(distance_matrix_parallel(&[]).unwrap().is_empty());
+        assert!(distance_matrix_parallel(&["CM87um", "KP04o"]).is_err());
+    }
+
+    #[test]
+    fn bearing_signed() {
+        // Westward
+        let signed = grid_bearing_signed("FN20xr", "CM87um").unwrap();
+        assert!(signed < 0.0);
+        assert_delta!(
+            signed + 360.0,
+            grid_bearing("FN20xr", "CM87um").unwrap(),
+            1e-9
+        );
+        assert_delta!(grid_bearing_signed("JJ00aa", "IJ90xa").unwrap(), -90.0, 0.1);
+
+        // Eastward is unchanged
+        let signed = grid_bearing_signed("CM87um", "FN20xr").unwrap();
+        assert_eq!(signed, grid_bearing("CM87um", "FN20xr").unwrap());
+        // Due south is 180, never -180
+        assert_delta!(
+            grid_bearing_signed("JJ00aa", "JI00aa").unwrap(),
+            180.0,
+            1e-9
+        );
+
+        assert!(grid_bearing_signed("JJ00a", "JI00aa").is_err());
+    }
+
+    #[test]
+    fn at_fraction() {
+        assert_eq!(
+            grid_at_fraction("CM87um", "FN20xr", 0.0, 6).unwrap(),
+            "CM87um"
+        );
+        assert_eq!(
+            grid_at_fraction("CM87um", "FN20xr", 1.0, 6).unwrap(),
+            "FN20xr"
+        );
+        assert_eq!(
+            grid_at_fraction("CM87um", "FN20xr", 0.5, 6).unwrap(),
+            grid_midpoint("CM87um", "FN20xr", 6).unwrap()
+        );
+        let path = great_circle_path("CM87um", "FN20xr", 4).unwrap();
+        assert_eq!(
+            grid_at_fraction("CM87um", "FN20xr", 0.25, 8).unwrap(),
+            longlat_to_grid(path[1].0, path[1].1, 8).unwrap()
+        );
+        assert_eq!(
+            grid_at_fraction("FN20xr", "FN20xr", 0.5, 6).unwrap(),
+            "FN20xr"
+        );
+
+        assert!(matches!(
+            grid_at_fraction("CM87um", "FN20xr", 1.5, 6),
+            Err(MHError::InvalidFraction(_))
+        ));
+        assert!(grid_at_fraction("CM87um", "FN20xr", f64::NAN, 6).is_err());
+        assert!(grid_at_fraction("KP04ow", "BC05ob", 0.5, 6).is_err());
+    }
+
+    #[test]
+    fn world_edges() {
+        for grid in ["AA", "RR", "AJ", "RI", "AJ05", "RI95", "RI99xk", "aj05AK"] {
+            assert!(grid_crosses_antimeridian(grid).unwrap(), "{}", grid);
+        }
+        for grid in ["BA", "QR", "AJ15", "RI85", "RI99wk", TEST_GRID] {
+            assert!(!grid_crosses_antimeridian(grid).unwrap(), "{}", grid);
+        }
+
+        for grid in ["AA", "RR", "JA", "JR", "JA00", "JR09", "JR59ax", "JA50ma"] {
+            assert!(grid_touches_pole(grid).unwrap(), "{}", grid);
+        }
+        for grid in ["AB", "RQ", "JA01", "JR08", "JR59aw", "JA50mb", TEST_GRID] {
+            assert!(!grid_touches_pole(grid).unwrap(), "{}", grid);
+        }
+
+        assert!(grid_crosses_antimeridian("AA0").is_err());
+        assert!(grid_touches_pole("AA0").is_err());
+    }
+
+    #[test]
+    fn corner_plus_half_cell() {
+        assert_eq!(grid_to_corner("FM18").unwrap(), (-78.0, 38.0));
+        for len in (2..=12).step_by(2) {
+            let grid = &TEST_GRID[..len];
+            let (long, lat) = grid_to_corner(grid).unwrap();
+            let (width, height) = cell_size(len).unwrap();
+            assert_eq!(
+                (long + width / 2.0, lat + height / 2.0),
+                grid_to_longlat(grid).unwrap()
+            );
+            assert_eq!((long, lat), grid_southwest_corner(grid).unwrap());
+        }
+        assert!(grid_to_corner("FM18l").is_err());
+    }
+
+    #[test]
+    fn grids_list() {
+        let results: Vec<Result<(f64, f64), MHError>> =
+            parse_grids("  FM18lv\tKP04  FM18l\n\nJJ00aa zz00 ").collect();
+        assert_eq!(results.len(), 5);
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            &grid_to_longlat("FM18lv").unwrap()
+        );
+        assert_eq!(
+            results[1].as_ref().unwrap(),
+            &grid_to_longlat("KP04").unwrap()
+        );
+        assert!(matches!(results[2], Err(MHError::InvalidGridLength(5))));
+        assert_eq!(
+            results[3].as_ref().unwrap(),
+            &grid_to_longlat("JJ00aa").unwrap()
+        );
+        assert!(matches!(results[4], Err(MHError::InvalidGridChar { .. })));
+
+        assert_eq!(parse_grids("").count(), 0);
+        assert_eq!(parse_grids(" \n ").count(), 0);
+    }
+
+    #[test]
+    fn boundary_offsets() {
+        // Exactly 180/90 would otherwise encode one past the last character of
+        // every position, e.g. a 'y' subsquare
+        assert_eq!(longlat_to_grid(180.0, 90.0, 12).unwrap(), "RR99xx99XX99");
+        assert_eq!(longlat_to_grid(179.99, 90.0, 6).unwrap(), "RR99xx");
+        assert_eq!(longlat_to_grid(180.0, 0.0, 6).unwrap(), "RJ90xa");
+
+        // Just below the edge of cells at every level, where the modulo math
+        // is closest to overshooting
+        let below = |v: f64| f64::from_bits(v.to_bits() - 1);
+        for (long_size, lat_size) in LONG_MULT.iter().zip(LAT_MULT) {
+            for k in 1..50 {
+                let long = below(k as f64 * long_size).min(180.0);
+                let lat = below(k as f64 * lat_size).min(90.0);
+                let grid = longlat_to_grid(long, lat, 12).unwrap();
+                assert!(is_valid_grid(&grid), "{} {} {}", long, lat, grid);
+                let grid = longlat_to_grid(-long, -lat, 12).unwrap();
+                assert!(is_valid_grid(&grid), "{} {} {}", -long, -lat, grid);
+            }
+        }
+    }
+
+    #[test]
+    fn confidence() {
+        for (len, confidence) in [
+            (2, GridConfidence::Low),
+            (4, GridConfidence::Low),
+            (6, GridConfidence::Medium),
+            (8, GridConfidence::High),
+            (10, GridConfidence::High),
+            (12, GridConfidence::High),
+        ] {
+            assert_eq!(grid_confidence(&TEST_GRID[..len]).unwrap(), confidence);
+        }
+        assert!(GridConfidence::Low < GridConfidence::High);
+        assert!(grid_confidence("FM18l").is_err());
+    }
+
+    #[test]
+    fn components() {
+        let parts = grid_components("fm18LV53sl").unwrap();
+        assert_eq!(
+            parts,
+            GridComponents {
+                field: "FM".to_string(),
+                square: Some("18".to_string()),
+                subsquare: Some("lv".to_string()),
+                extended: Some("53".to_string()),
+                superextended: Some("SL".to_string()),
+                hyperextended: None,
+            }
+        );
+
+        let parts = grid_components("FM18").unwrap();
+        assert_eq!(parts.field, "FM");
+        assert_eq!(parts.square.as_deref(), Some("18"));
+        assert_eq!(parts.subsquare, None);
+        assert_eq!(parts.extended, None);
+        assert_eq!(parts.superextended, None);
+
+        assert_eq!(
+            grid_components(TEST_GRID).unwrap().hyperextended.as_deref(),
+            Some("34")
+        );
+        assert_eq!(grid_components("FM").unwrap().square, None);
+        assert!(grid_components("FM18l").is_err());
+    }
+
+    #[test]
+    fn distance_calculator() {
+        let calc = GridDistanceCalculator::new("CM87um").unwrap();
+        for grid in ["KP04ow", "FN20xr", "PM95", "CM87um", TEST_GRID] {
+            assert_eq!(
+                calc.distance_to(grid).unwrap(),
+                grid_distance("CM87um", grid).unwrap()
+            );
+            assert_eq!(
+                calc.bearing_to(grid).unwrap(),
+                grid_bearing("CM87um", grid).unwrap()
+            );
+        }
+        assert!(calc.distance_to("KP04o").is_err());
+        assert!(calc.bearing_to("KP04o").is_err());
+        assert!(GridDistanceCalculator::new("CM87u").is_err());
+    }
+
+    #[test]
+    fn punctuation_and_control() {
+        for grid in [
+            "!!00", "\0\0", "A\u{1}", "FM1!", "FM18!!", " FM18", "FM\t8", "@@", "FM18`v", "🤷🤷",
+        ] {
+            assert!(!is_valid_grid(grid), "{:?}", grid);
+            let ret = grid_to_longlat(grid);
+            assert!(
+                matches!(
+                    ret,
+                    Err(MHError::InvalidGridChar { .. }) | Err(MHError::InvalidGridLength(_))
+                ),
+                "{:?}",
+                grid
+            );
+            assert!(grid_bounds(grid).is_err());
+            assert!(normalize_grid(grid).is_err());
+            assert!(grid_neighbors(grid).is_err());
+        }
+
+        // Characters just below the start of each range
+        assert!(matches!(
+            grid_to_longlat("@A00"),
+            Err(MHError::InvalidGridChar {
+                position: 0,
+                found: '@',
+                ..
+            })
+        ));
+        assert!(matches!(
+            grid_to_longlat("AA/0"),
+            Err(MHError::InvalidGridChar {
+                position: 2,
+                found: '/',
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn to_grid_trait() {
+        assert_eq!((TEST_LONG, TEST_LAT).to_grid(6).unwrap(), "FM18lv");
+        assert_eq!((TEST_LONG, TEST_LAT).to_grid(12).unwrap(), TEST_GRID);
+        let grids: Vec<String> = [(0.0, 0.0), (TEST_LONG, TEST_LAT)]
+            .iter()
+            .map(|c| c.to_grid(4))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(grids, ["JJ00", "FM18"]);
+
+        assert!((TEST_LONG, TEST_LAT).to_grid(5).is_err());
+        assert!((0.0, 91.0).to_grid(6).is_err());
+    }
+
+    #[test]
+    fn offset() {
+        // Three columns apart
+        assert_eq!(grid_offset("FN31", "FN61").unwrap(), (3, 0));
+        assert_eq!(grid_offset("FN61", "FN31").unwrap(), (-3, 0));
+        assert_eq!(grid_offset("fn31PR", "FN31su").unwrap(), (3, 3));
+        assert_eq!(grid_offset("FN31", "FM28").unwrap(), (-1, -3));
+        assert_eq!(grid_offset("FN31", "FN31").unwrap(), (0, 0));
+
+        // The shorter way across the antimeridian
+        assert_eq!(grid_offset("RI99", "AI09").unwrap(), (1, 0));
+        assert_eq!(grid_offset("AI09", "RI89").unwrap(), (-2, 0));
+        assert_eq!(grid_offset("AA", "JA").unwrap(), (9, 0));
+
+        assert!(matches!(
+            grid_offset("FN31", "FN31pr"),
+            Err(MHError::PrecisionMismatch(4, 6))
+        ));
+        assert!(grid_offset("FN31", "FN3").is_err());
+    }
+
+    #[test]
+    fn children() {
+        for (parent, count) in [
+            ("fm", 100),
+            ("FM18", 576),
+            ("FM18lv", 100),
+            ("FM18lv53", 576),
+            ("FM18lv53SL", 100),
+        ] {
+            let children = subgrids(parent).unwrap();
+            assert_eq!(children.len(), count, "{}", parent);
+            for child in &children {
+                assert_eq!(child.len(), parent.len() + 2);
+                assert!(
+                    grid_contains(parent, child).unwrap(),
+                    "{} {}",
+                    parent,
+                    child
+                );
+            }
+            let unique: alloc::collections::BTreeSet<&String> = children.iter().collect();
+            assert_eq!(unique.len(), count);
+        }
+        let children = subgrids("FM18").unwrap();
+        assert_eq!(children[0], "FM18aa");
+        assert_eq!(children[1], "FM18ba");
+        assert_eq!(children[575], "FM18xx");
+
+        assert!(matches!(
+            subgrids(TEST_GRID),
+            Err(MHError::InvalidPrecision(14))
+        ));
+        assert!(subgrids("FM18l").is_err());
+    }
+
+    #[test]
+    fn strict() {
+        assert_eq!(parse_grid_strict("FM18lv").unwrap(), "FM18lv");
+        assert_eq!(parse_grid_strict(TEST_GRID).unwrap(), TEST_GRID);
+        for grid in ["FM18LV", "fm18lv", "Fm18lv", "FM18lV", "FM18lv53sl"] {
+            assert!(
+                matches!(parse_grid_strict(grid), Err(MHError::InvalidGridCase(_))),
+                "{}",
+                grid
+            );
+        }
+        // While the default parsing accepts them
+        assert_eq!(normalize_grid("FM18LV").unwrap(), "FM18lv");
+        assert!(grid_to_longlat("FM18LV").is_ok());
+
+        assert!(matches!(
+            parse_grid_strict("FM18l"),
+            Err(MHError::InvalidGridLength(5))
+        ));
+    }
+
+    #[test]
+    fn diagnose() {
+        // Extended square digits where the subsquare letters belong
+        let diagnosis = diagnose_grid("FM185399");
+        assert!(diagnosis.length_valid);
+        assert!(!diagnosis.is_valid());
+        assert_eq!(
+            diagnosis.bad_positions,
+            [
+                BadPosition {
+                    position: 4,
+                    found: '5',
+                    expected: CharClass::Letter
+                },
+                BadPosition {
+                    position: 5,
+                    found: '3',
+                    expected: CharClass::Letter
+                },
+            ]
+        );
+
+        // The length is in characters, not bytes
+        let diagnosis = diagnose_grid("FM18é");
+        assert!(!diagnosis.length_valid);
+        assert_eq!(diagnosis.bad_positions.len(), 1);
+        assert_eq!(diagnosis.bad_positions[0].position, 4);
+        assert!(diagnose_grid("FM18éé").length_valid);
+
+        let diagnosis = diagnose_grid("Z1l");
+        assert!(!diagnosis.length_valid);
+        let expected: Vec<(usize, CharClass)> = diagnosis
+            .bad_positions
+            .iter()
+            .map(|b| (b.position, b.expected))
+            .collect();
+        assert_eq!(
+            expected,
+            [
+                (0, CharClass::FieldLetter),
+                (1, CharClass::FieldLetter),
+                (2, CharClass::Digit)
+            ]
+        );
+
+        for grid in ["FM18lv", TEST_GRID, "rr99XX"] {
+            assert!(diagnose_grid(grid).is_valid(), "{}", grid);
+            assert_eq!(diagnose_grid(grid).is_valid(), is_valid_grid(grid));
+        }
+        assert!(!diagnose_grid("").is_valid());
+        assert_eq!(CharClass::Digit.to_string(), "a digit 0-9");
+    }
+
+    #[test]
+    fn centroid() {
+        // Straddling the antimeridian ends up next to it, not at 0 longitude
+        assert_eq!(grids_centroid(&["RI89", "AI09"], 4).unwrap(), "RI99");
+        let grid = grids_centroid(&["RI89mm", "AI09mm", "RJ80mm", "AJ00mm"], 2).unwrap();
+        assert!(grid.starts_with('R') || grid.starts_with('A'), "{}", grid);
+
+        assert_eq!(grids_centroid(&["FN20xr"], 6).unwrap(), "FN20xr");
+        assert_eq!(
+            grids_centroid(&["CM87um", "FN20xr"], 6).unwrap(),
+            grid_midpoint("CM87um", "FN20xr", 6).unwrap()
+        );
+
+        assert!(matches!(grids_centroid(&[], 6), Err(MHError::NoGrids)));
+        assert!(grids_centroid(&["FN20xr", "FN20x"], 6).is_err());
+        assert!(grids_centroid(&["FN20xr"], 5).is_err());
+    }
+
+    #[test]
+    fn grid_default() {
+        let grid = Grid::default();
+        assert_eq!(grid.as_str(), "JJ00");
+        assert_eq!(grid, Grid::from_coords(0.0, 0.0, 4).unwrap());
+        // 0/0 is the corner of the square, its center is half a square off
+        assert_eq!(grid_to_corner(grid.as_str()).unwrap(), (0.0, 0.0));
+        assert_eq!(grid.to_longlat(), (1.0, 0.5));
+
+        #[derive(Default)]
+        struct Station {
+            grid: Grid,
+        }
+        assert_eq!(Station::default().grid.precision(), 4);
+    }
+
+    #[test]
+    fn snap() {
+        // Nearby points in the same cell snap to the same center
+        let a = snap_to_grid_center(TEST_LONG, TEST_LAT, 6).unwrap();
+        let b = snap_to_grid_center(TEST_LONG + 0.01, TEST_LAT - 0.01, 6).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a, grid_to_longlat("FM18lv").unwrap());
+
+        // But not in a finer one
+        let a = snap_to_grid_center(TEST_LONG, TEST_LAT, 10).unwrap();
+        let b = snap_to_grid_center(TEST_LONG + 0.01, TEST_LAT - 0.01, 10).unwrap();
+        assert!(a != b);
+
+        assert_eq!(snap_to_grid_center(-78.0, 38.0, 4).unwrap(), (-77.0, 38.5));
+        assert!(snap_to_grid_center(TEST_LONG, TEST_LAT, 5).is_err());
+        assert!(snap_to_grid_center(TEST_LONG, 95.0, 6).is_err());
+    }
+
+    #[cfg(feature = "solar")]
+    #[test]
+    fn solar() {
+        // Local solar noon at the March 2024 equinox: due south, at 90 degrees
+        // less the latitude.
+        let (azimuth, elevation) = solar_position("JO01", 1710936210).unwrap();
+        assert_delta!(azimuth, 180.0, 1.0);
+        assert_delta!(elevation, 38.5, 1.0);
+
+        // Midnight, same place
+        let (azimuth, elevation) = solar_position("JO01", 1710893010).unwrap();
+        // Due north, either side of 0
+        assert_delta!((azimuth + 180.0) % 360.0, 180.0, 1.0);
+        assert_delta!(elevation, -38.5, 1.0);
+
+        // Subsolar point at the June 2024 solstice
+        let (_, elevation) = solar_position("CL33", 1718916660).unwrap();
+        assert!(elevation > 89.0);
+
+        assert!(solar_position("JO0", 1710936210).is_err());
+    }
+
+    #[test]
+    fn step() {
+        assert_eq!(step_grid("FM18lv", 0, 0).unwrap(), "FM18lv");
+        assert_eq!(step_grid("FM18", 3, -2).unwrap(), "FM46");
+        assert_eq!(step_grid("fm18lv", -1, 1).unwrap(), "FM18kw");
+
+        // Across the antimeridian, both ways
+        assert_eq!(step_grid("RL99", 1, 0).unwrap(), "AL09");
+        assert_eq!(step_grid("AL09", -2, 0).unwrap(), "RL89");
+        // All the way around
+        assert_eq!(step_grid("JJ00", 180, 0).unwrap(), "JJ00");
+        assert_eq!(
+            step_grid("JJ00", i64::MIN, 0).unwrap(),
+            step_grid("JJ00", i64::MIN % 180, 0).unwrap()
+        );
+        assert!(step_grid("JJ00", i64::MAX, 0).is_ok());
+
+        // Over a pole
+        assert!(matches!(
+            step_grid("JR09", 0, 1),
+            Err(MHError::InvalidLongLat(_, _))
+        ));
+        assert!(step_grid("JA00", 0, -1).is_err());
+        assert!(step_grid("JJ00", 0, i64::MAX).is_err());
+        assert!(step_grid("JJ00", 0, i64::MIN).is_err());
+        assert_eq!(step_grid("JA00", 0, 179).unwrap(), "JR09");
+
+        assert!(step_grid("FM1", 1, 0).is_err());
+    }
+
+    #[test]
+    fn cell_dimensions() {
+        // A degree of latitude is about 111 km
+        let (width, height) = cell_dimensions_km("JJ00").unwrap();
+        assert_delta!(width, 222.0, 0.5);
+        assert_delta!(height, 111.0, 0.5);
+
+        // Narrower toward the poles, but just as high
+        let (north_width, north_height) = cell_dimensions_km("JP00").unwrap();
+        let (polar_width, polar_height) = cell_dimensions_km("JR09").unwrap();
+        assert!(polar_width < north_width && north_width < width);
+        assert_delta!(
+            north_width / width,
+            60.5f64.to_radians().cos() / 0.5f64.to_radians().cos(),
+            1e-9
+        );
+        assert_eq!(north_height, height);
+        assert_eq!(polar_height, height);
+
+        assert!(cell_dimensions_km("JJ0").is_err());
+    }
+
+    #[test]
+    fn longlat_prefix() {
+        let full = grid_to_longlat("FM18lv53SL00").unwrap();
+        assert_eq!(grid_to_longlat_prefix("FM18lv53SL00").unwrap(), full);
+        assert_eq!(grid_to_longlat_prefix("FM18lv53SL00ax").unwrap(), full);
+        assert_eq!(grid_to_longlat_prefix("FM18lv53SL00a").unwrap(), full);
+        assert_eq!(grid_to_longlat_prefix("FM18lv53SL00!?é").unwrap(), full);
+        assert!(grid_to_longlat("FM18lv53SL00ax").is_err());
+
+        assert_eq!(
+            grid_to_longlat_prefix("FM18lv53S").unwrap(),
+            grid_to_longlat("FM18lv53").unwrap()
+        );
+        assert_eq!(
+            grid_to_longlat_prefix("FM18l").unwrap(),
+            grid_to_longlat("FM18").unwrap()
+        );
+
+        // Only what's beyond the supported precisions is ignored
+        assert!(grid_to_longlat_prefix("FM18lz53SL00ax").is_err());
+        assert!(grid_to_longlat_prefix("F").is_err());
+        assert!(grid_to_longlat_prefix("").is_err());
+    }
+
+    #[test]
+    fn area_wgs84() {
+        for grid in ["JJ00", "FN31pr", "KP20", "JR09", "RA90xx"] {
+            let spherical = grid_area_km2(grid).unwrap();
+            let ellipsoidal = grid_area_wgs84_km2(grid).unwrap();
+            assert_ne!(spherical, ellipsoidal);
+            assert!((spherical - ellipsoidal).abs() / spherical < 0.01);
+        }
+        // Compared to the sphere, there's less area near the equator and more
+        // near the poles
+        assert!(grid_area_wgs84_km2("JJ00").unwrap() < grid_area_km2("JJ00").unwrap());
+        assert!(grid_area_wgs84_km2("JR09").unwrap() > grid_area_km2("JR09").unwrap());
+
+        // All fields add up to the surface area of the ellipsoid
+        let total: f64 = all_grids(2)
+            .unwrap()
+            .map(|g| grid_area_wgs84_km2(&g).unwrap())
+            .sum();
+        assert_delta!(total, 510_065_621.7, 1.0);
+
+        assert!(grid_area_wgs84_km2("JJ0").is_err());
+    }
+
+    #[test]
+    fn longlat_batch() {
+        let grids = longlat_batch_to_grids(
+            &[
+                (-122.3, 37.6),
+                (181.0, 0.0),
+                (1.0, 0.5),
+                (0.0, f64::NAN),
+                (-72.0, 41.7),
+            ],
+            6,
+        );
+        assert_eq!(grids.len(), 5);
+        assert_eq!(grids[0].as_ref().unwrap(), "CM87uo");
+        assert!(matches!(grids[1], Err(MHError::InvalidLongLat(_, _))));
+        assert_eq!(grids[2].as_ref().unwrap(), "JJ00mm");
+        assert!(matches!(grids[3], Err(MHError::InvalidLongLat(_, _))));
+        assert_eq!(grids[4].as_ref().unwrap(), "FN41aq");
+
+        assert!(longlat_batch_to_grids(&[], 6).is_empty());
+        assert!(longlat_batch_to_grids(&[(1.0, 0.5)], 5)[0].is_err());
+    }
+
+    #[cfg(feature = "num-traits")]
+    #[test]
+    fn longlat_generic() {
+        for grid in ["FM18lv53SL", "CM87uo44AX", "RA90xx99XA", "AR09aa00AA"] {
+            let (long, lat) = grid_to_longlat(grid).unwrap();
+            for precision in [2, 4, 6, 8, 10] {
+                let grid = longlat_to_grid(long, lat, precision).unwrap();
+                assert_eq!(longlat_to_grid_generic(long, lat, precision).unwrap(), grid);
+                // Rounding to an f32 doesn't move these out of the grid
+                assert_eq!(
+                    longlat_to_grid_generic(long as f32, lat as f32, precision).unwrap(),
+                    grid
+                );
+            }
+        }
+
+        // But a coordinate on a grid edge can round across it
+        assert_eq!(longlat_to_grid(-122.3, 37.6, 8).unwrap(), "CM87uo44");
+        assert_eq!(
+            longlat_to_grid_generic(-122.3f32, 37.6, 8).unwrap(),
+            "CM87uo33"
+        );
+
+        assert!(longlat_to_grid_generic(181.0f32, 0.0, 6).is_err());
+        assert!(longlat_to_grid_generic(0.0f32, f32::NAN, 6).is_err());
+        assert!(longlat_to_grid_generic(0.0f64, 0.0, 5).is_err());
+    }
+
+    #[test]
+    fn rhumb() {
+        // East-west at 60° north: due east the whole way, but noticeably
+        // longer than the great circle, which veers north
+        let rhumb = grid_rhumb_distance("FP00", "KP00").unwrap();
+        let great_circle = grid_distance("FP00", "KP00").unwrap();
+        assert_delta!(grid_rhumb_bearing("FP00", "KP00").unwrap(), 90.0, 1e-9);
+        assert_delta!(grid_rhumb_bearing("KP00", "FP00").unwrap(), 270.0, 1e-9);
+        assert!(grid_bearing("FP00", "KP00").unwrap() < 45.0);
+        assert!(rhumb > great_circle * 1.1);
+        // Along the parallel, which at 60.5° is cos(60.5°) of the equator
+        assert_delta!(
+            rhumb,
+            RADIUS * 100f64.to_radians() * 60.5f64.to_radians().cos(),
+            1e-6
+        );
+
+        // Across the antimeridian the short way
+        assert_delta!(grid_rhumb_bearing("RJ90", "AJ00").unwrap(), 90.0, 1e-9);
+        assert_delta!(
+            grid_rhumb_distance("RJ90", "AJ00").unwrap(),
+            grid_distance("RJ90", "AJ00").unwrap(),
+            1e-6
+        );
+
+        // Along a meridian they're the same
+        assert_delta!(
+            grid_rhumb_distance("JJ00", "JN00").unwrap(),
+            grid_distance("JJ00", "JN00").unwrap(),
+            1e-6
+        );
+        assert_delta!(grid_rhumb_bearing("JJ00", "JN00").unwrap(), 0.0, 1e-9);
+        assert_delta!(grid_rhumb_bearing("JN00", "JJ00").unwrap(), 180.0, 1e-9);
+
+        // Diagonally, somewhere in between
+        let rhumb = grid_rhumb_distance("FN31pr", "JO01").unwrap();
+        let great_circle = grid_distance("FN31pr", "JO01").unwrap();
+        assert!(rhumb > great_circle && rhumb < great_circle * 1.1);
+
+        assert_eq!(grid_rhumb_distance("FN31pr", "FN31pr").unwrap(), 0.0);
+        assert!(grid_rhumb_distance("FN31p", "JO01").is_err());
+        assert!(grid_rhumb_bearing("FN31pr", "JO0").is_err());
+    }
+
+    #[test]
+    fn latlong_order() {
+        let (long, lat) = grid_to_longlat("FM18lv").unwrap();
+        assert_eq!(grid_to_latlong("FM18lv").unwrap(), (lat, long));
+        assert!(grid_to_latlong("FM18l").is_err());
+
+        assert_eq!(
+            latlong_to_grid(TEST_LAT, TEST_LONG, 6).unwrap(),
+            longlat_to_grid(TEST_LONG, TEST_LAT, 6).unwrap()
+        );
+        assert_eq!(latlong_to_grid(TEST_LAT, TEST_LONG, 6).unwrap(), "FM18lv");
+        // 91 is only a valid longitude
+        assert!(latlong_to_grid(91.0, 0.0, 6).is_err());
+        assert!(longlat_to_grid(91.0, 0.0, 6).is_ok());
+    }
+
+    #[test]
+    fn microdeg() {
+        assert_eq!(grid_to_microdeg("JJ00").unwrap(), (1_000_000, 500_000));
+        assert_eq!(grid_to_microdeg("AA").unwrap(), (-170_000_000, -85_000_000));
+        assert_eq!(grid_to_microdeg("RR").unwrap(), (170_000_000, 85_000_000));
+
+        for grid in [
+            "FM18lv",
+            "CM87uo44",
+            "RR99xx99XX",
+            "AA00aa00AA00",
+            "FM18lv53SL00",
+        ] {
+            let (long, lat) = grid_to_longlat(grid).unwrap();
+            let (long_micro, lat_micro) = grid_to_microdeg(grid).unwrap();
+            assert_delta!(long_micro as f64 / 1e6, long, 1e-6);
+            assert_delta!(lat_micro as f64 / 1e6, lat, 1e-6);
+            assert_eq!(
+                longlat_to_grid(long_micro as f64 / 1e6, lat_micro as f64 / 1e6, grid.len())
+                    .unwrap(),
+                grid
+            );
+        }
+
+        assert!(grid_to_microdeg("FM18l").is_err());
     }
 }